@@ -14,5 +14,5 @@ fn panic(msg: Option<String>) -> String {
 fn rocket() -> Rocket<Build> {
     rocket::build()
         .attach(RocketSentry::fairing())
-        .mount("/", routes![panic])
+        .mount("/", rocket_sentry::bind_hub(routes![panic]))
 }
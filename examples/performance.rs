@@ -2,9 +2,7 @@
 extern crate rocket;
 
 use rocket::{Build, Rocket};
-use rocket_sentry::RocketSentry;
-use sentry::{Hub, TransactionContext, TransactionOrSpan};
-use std::sync::Arc;
+use rocket_sentry::{RocketSentry, SamplingDecision, SentryTransaction};
 use std::thread;
 use std::time::Duration;
 
@@ -41,23 +39,15 @@ fn performance_skipped() -> String {
 fn performance_rng() -> String {
     let duration = Duration::from_millis(100);
     thread::sleep(duration);
-    format!("Waited {duration:?}\nTransaction MIGHT be dropped")
+    format!("Waited {duration:?}\nTransaction is sent half the time")
 }
 
 #[get("/performance/multiple_spans")]
-fn performance_with_multiple_spans() -> String {
+fn performance_with_multiple_spans(transaction: SentryTransaction<'_>) -> String {
     let duration = Duration::from_millis(150);
     thread::sleep(duration);
-    let parent = Hub::current().configure_scope(|scope| scope.get_span());
     let op_name = "some operation";
-    let child_1_name = "child 1";
-    let child_1: TransactionOrSpan = match parent {
-        Some(parent) => parent.start_child(op_name, child_1_name).into(),
-        None => {
-            let context = TransactionContext::new(child_1_name, op_name);
-            sentry::start_transaction(context).into()
-        }
-    };
+    let child_1 = transaction.start_child(op_name, "child 1");
 
     thread::sleep(duration);
     sentry::capture_message("some message", sentry::Level::Warning);
@@ -65,45 +55,33 @@ fn performance_with_multiple_spans() -> String {
     thread::sleep(duration);
     child_2.finish();
     child_1.finish();
+    transaction.set_tag("spans", 2);
     "Waited some time with multiple spans".to_string()
 }
 
 #[launch]
 fn rocket() -> Rocket<Build> {
-    let rocket_instance = rocket::build();
-    // Get the default configured sample rate from `Rocket.toml`
-    let default_rate = rocket_instance
-        .figment()
-        .extract_inner::<f32>("sentry_traces_sample_rate")
-        .unwrap();
-    let traces_sampler = move |ctx: &TransactionContext| -> f32 {
-        match ctx.name() {
-            "GET /performance/skip" => {
-                log::warn!("Dropping performance transaction");
-                0.
-            }
-            "GET /performance/random" => {
-                log::warn!("Sending performance transaction half the time");
-                0.
-            }
-            _ => {
-                log::warn!("Sending performance transaction using default rate");
-                default_rate
-            }
-        }
-    };
+    // The sample rates of `/performance/skip` and `/performance/random` are set in `Rocket.toml`,
+    // requests with an `X-Debug` header are always sent
     let rocket_sentry = RocketSentry::builder()
-        .traces_sampler(Arc::new(traces_sampler))
+        .request_sampler(|request| {
+            if request.headers().contains("X-Debug") {
+                SamplingDecision::Keep
+            } else {
+                SamplingDecision::Defer
+            }
+        })
         .build();
-    rocket_instance.attach(rocket_sentry).mount(
+    // Binding the request hub lets events captured in handlers belong to the request transaction
+    rocket::build().attach(rocket_sentry).mount(
         "/",
-        routes![
+        rocket_sentry::bind_hub(routes![
             performance,
             performance_with_id,
             performance_with_parameter,
             performance_skipped,
             performance_rng,
             performance_with_multiple_spans,
-        ],
+        ]),
     )
 }
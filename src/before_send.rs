@@ -0,0 +1,232 @@
+//! User callbacks for events and transactions about to be sent, which get to see the Rocket
+//! request they originated from.
+//!
+//! Sentry calls `before_send` and sends transactions synchronously on the thread capturing them,
+//! so the request is handed over through thread-locals. The transport wrapper that runs
+//! `before_send_transaction` also passes on the dynamic sampling context of incoming traces.
+
+use std::cell::RefCell;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::http::{HeaderMap, Method};
+use rocket::Request;
+use sentry::protocol::{DynamicSamplingContext, EnvelopeItem, Event, Transaction};
+use sentry::types::Uuid;
+use sentry::{ClientOptions, Envelope, Transport, TransportFactory};
+
+use crate::SentryHandle;
+
+/// Filters or modifies events before they are sent, see
+/// [`RocketSentryBuilder::before_send`](crate::RocketSentryBuilder::before_send).
+pub(crate) type BeforeSend =
+    dyn Fn(Event<'static>, Option<&RequestMetadata>) -> Option<Event<'static>> + Send + Sync;
+
+/// Filters or modifies transactions before they are sent, see
+/// [`RocketSentryBuilder::before_send_transaction`](crate::RocketSentryBuilder::before_send_transaction).
+pub(crate) type BeforeSendTransaction = dyn Fn(Transaction<'static>, Option<&RequestMetadata>) -> Option<Transaction<'static>>
+    + Send
+    + Sync;
+
+/// The Rocket request that an event or transaction originated from.
+///
+/// Unlike the request reported to Sentry, the query string and headers aren't scrubbed.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RequestMetadata {
+    pub method: Method,
+    /// The path of the request URI, still percent-encoded.
+    pub path: String,
+    /// The query string of the request URI, still percent-encoded.
+    pub query: Option<String>,
+    pub headers: HeaderMap<'static>,
+    /// See [`RocketSentryBuilder::trusted_proxies`](crate::RocketSentryBuilder::trusted_proxies).
+    pub client_ip: Option<IpAddr>,
+}
+
+impl RequestMetadata {
+    pub(crate) fn new(request: &Request, client_ip: Option<IpAddr>) -> Self {
+        RequestMetadata {
+            method: request.method(),
+            path: request.uri().path().to_string(),
+            query: request.uri().query().map(|query| query.to_string()),
+            headers: request
+                .headers()
+                .iter()
+                .fold(HeaderMap::new(), |mut headers, header| {
+                    headers.add_raw(header.name.to_string(), header.value.into_owned());
+                    headers
+                }),
+            client_ip,
+        }
+    }
+}
+
+/// The metadata of a request in flight, if any callbacks need it.
+pub(crate) struct CachedRequestMetadata(pub(crate) Option<Arc<RequestMetadata>>);
+
+/// Where the transactions being finished on a thread came from.
+#[derive(Clone, Default)]
+pub(crate) struct TransactionOrigin {
+    /// The request the transaction was started for, if any callbacks need it.
+    pub(crate) request: Option<Arc<RequestMetadata>>,
+    /// The dynamic sampling context of the incoming trace, which Sentry wants passed on
+    /// unchanged instead of the one the SDK fills in.
+    pub(crate) dynamic_sampling_context: Option<DynamicSamplingContext>,
+}
+
+thread_local! {
+    /// The request of the event being processed on this thread, by event ID.
+    static EVENT_REQUEST: RefCell<Option<(Uuid, Arc<RequestMetadata>)>> = const { RefCell::new(None) };
+    /// The origin of the transactions being finished on this thread.
+    static TRANSACTION_ORIGIN: RefCell<Option<TransactionOrigin>> = const { RefCell::new(None) };
+}
+
+/// Remembers the request of an event, for `before_send` called later while capturing it.
+pub(crate) fn set_event_request(event_id: Uuid, request: Arc<RequestMetadata>) {
+    EVENT_REQUEST.with(|current| *current.borrow_mut() = Some((event_id, request)));
+}
+
+fn take_event_request(event_id: Uuid) -> Option<Arc<RequestMetadata>> {
+    let (id, request) = EVENT_REQUEST.with(|current| current.borrow_mut().take())?;
+    // Events dropped before reaching `before_send` leave their request behind
+    (id == event_id).then_some(request)
+}
+
+/// Runs `finish` with the transactions it sends attributed to `origin`.
+pub(crate) fn with_transaction_origin<R>(
+    origin: TransactionOrigin,
+    finish: impl FnOnce() -> R,
+) -> R {
+    /// Restores the previous origin, also if `finish` panics.
+    struct Restore(Option<TransactionOrigin>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            TRANSACTION_ORIGIN.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(TRANSACTION_ORIGIN.with(|current| current.replace(Some(origin))));
+    finish()
+}
+
+/// Chains the user's `before_send` with the fairing's own.
+pub(crate) fn chain_before_send(
+    before_send: Option<Arc<BeforeSend>>,
+    handle: SentryHandle,
+) -> Arc<dyn Fn(Event<'static>) -> Option<Event<'static>> + Send + Sync> {
+    Arc::new(move |event| {
+        let event = match &before_send {
+            Some(before_send) => {
+                let request = take_event_request(event.event_id);
+                before_send(event, request.as_deref())?
+            }
+            None => event,
+        };
+        info!("Sending event to Sentry: {}", event.event_id);
+        handle.count_event();
+        Some(event)
+    })
+}
+
+/// Wraps the transport of the client options, to run `before_send_transaction` on transactions
+/// and to pass on the dynamic sampling context of incoming traces.
+pub(crate) fn wrap_transport(
+    options: &mut ClientOptions,
+    before_send_transaction: Option<Arc<BeforeSendTransaction>>,
+) {
+    let transport = options
+        .transport
+        .take()
+        .unwrap_or_else(|| Arc::new(sentry::transports::DefaultTransportFactory));
+    options.transport = Some(Arc::new(TransactionTransportFactory {
+        transport,
+        before_send_transaction,
+    }));
+}
+
+struct TransactionTransportFactory {
+    transport: Arc<dyn TransportFactory>,
+    before_send_transaction: Option<Arc<BeforeSendTransaction>>,
+}
+
+impl TransportFactory for TransactionTransportFactory {
+    // Newer Sentry versions deprecate this, but call it through their replacement by default
+    #[allow(deprecated)]
+    fn create_transport(&self, options: &ClientOptions) -> Arc<dyn Transport> {
+        Arc::new(TransactionTransport {
+            transport: self.transport.create_transport(options),
+            before_send_transaction: self.before_send_transaction.clone(),
+        })
+    }
+}
+
+struct TransactionTransport {
+    transport: Arc<dyn Transport>,
+    before_send_transaction: Option<Arc<BeforeSendTransaction>>,
+}
+
+impl Transport for TransactionTransport {
+    fn send_envelope(&self, envelope: Envelope) {
+        let is_transaction = |item: &EnvelopeItem| matches!(item, EnvelopeItem::Transaction(_));
+        if !envelope.items().any(is_transaction) {
+            return self.transport.send_envelope(envelope);
+        }
+
+        let origin = TRANSACTION_ORIGIN.with(|current| current.borrow().clone());
+        let origin = origin.unwrap_or_default();
+        let mut headers = envelope.headers().clone();
+        if let Some(dynamic_sampling_context) = origin.dynamic_sampling_context {
+            headers = headers.with_trace(dynamic_sampling_context);
+        }
+        let mut filtered = Envelope::new().with_headers(headers);
+        for item in envelope.into_items() {
+            match item {
+                EnvelopeItem::Transaction(transaction) => {
+                    let transaction = match &self.before_send_transaction {
+                        Some(before_send_transaction) => {
+                            before_send_transaction(transaction, origin.request.as_deref())
+                        }
+                        None => Some(transaction),
+                    };
+                    if let Some(transaction) = transaction {
+                        filtered.add_item(transaction);
+                    }
+                }
+                item => filtered.add_item(item),
+            }
+        }
+        // Whatever else the envelope held belongs to the dropped transaction
+        if filtered.items().any(is_transaction) {
+            self.transport.send_envelope(filtered);
+        }
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        self.transport.flush(timeout)
+    }
+
+    fn shutdown(&self, timeout: Duration) -> bool {
+        self.transport.shutdown(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use super::{with_transaction_origin, TransactionOrigin, TRANSACTION_ORIGIN};
+
+    #[test]
+    fn transaction_origin_restored_on_panic() {
+        let result = panic::catch_unwind(|| {
+            with_transaction_origin(TransactionOrigin::default(), || panic!("Finishing failed"));
+        });
+
+        assert!(result.is_err());
+        assert!(TRANSACTION_ORIGIN.with(|current| current.borrow().is_none()));
+    }
+}
@@ -0,0 +1,113 @@
+//! Capturing of request bodies, see `sentry_max_request_body_size`.
+//!
+//! Text bodies are captured up to the limit, while binary bodies are only summarised.
+
+use rocket::http::ContentType;
+use rocket::{Data, Request};
+use serde_json::Value;
+
+use crate::scrub::Scrubber;
+
+/// Rocket buffers at most this many bytes of a request body for peeking at, without consuming it.
+pub(crate) const MAX_REQUEST_BODY_SIZE: usize = 512;
+
+const CONTENT_LENGTH_HEADER: &str = "Content-Length";
+
+/// The captured body of a request in flight, already scrubbed.
+pub(crate) struct RequestBody(pub(crate) Option<String>);
+
+/// Peeks at the request body, leaving it intact for the handler.
+///
+/// JSON and form bodies are scrubbed field by field, other text bodies are kept as is, while
+/// binary bodies and bodies larger than `limit` are only summarised.
+pub(crate) async fn capture(
+    request: &Request<'_>,
+    data: &mut Data<'_>,
+    limit: usize,
+    scrubber: &Scrubber,
+) -> Option<String> {
+    // Larger limits are rejected on ignite
+    let limit = limit.min(MAX_REQUEST_BODY_SIZE);
+    // Peek one byte past the limit to tell whether the body fits
+    let len = data.peek(limit + 1).await.len();
+    if len == 0 {
+        return None;
+    }
+    let content_length = request
+        .headers()
+        .get_one(CONTENT_LENGTH_HEADER)
+        .and_then(|length| length.parse::<usize>().ok());
+    let complete = len <= limit && (data.peek_complete() || content_length == Some(len));
+    if !complete {
+        return Some(format!("[Omitted: larger than {limit} bytes]"));
+    }
+
+    let content_type = request.content_type().cloned();
+    let content_type = content_type.unwrap_or(ContentType::Binary);
+    let body = data.peek(limit).await;
+    Some(body_to_string(body, &content_type, scrubber))
+}
+
+fn body_to_string(body: &[u8], content_type: &ContentType, scrubber: &Scrubber) -> String {
+    let summary = || format!("[Omitted: {} bytes of {content_type}]", body.len());
+    let Ok(text) = std::str::from_utf8(body) else {
+        return summary();
+    };
+    if content_type.is_json() {
+        let Ok(mut value) = serde_json::from_str::<Value>(text) else {
+            return summary();
+        };
+        scrubber.json(&mut value);
+        value.to_string()
+    } else if content_type.is_form() {
+        scrubber.query_string(text)
+    } else if content_type.top() == "text" {
+        text.to_string()
+    } else {
+        summary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::ContentType;
+
+    use super::body_to_string;
+    use crate::scrub::Scrubber;
+
+    #[test]
+    fn json_body() {
+        let body = br#"{"user":"a","password":"b"}"#;
+
+        let data = body_to_string(body, &ContentType::JSON, &Scrubber::default());
+
+        assert_eq!(data, r#"{"password":"[Filtered]","user":"a"}"#);
+    }
+
+    #[test]
+    fn form_body() {
+        let body = b"user=a&password=b";
+
+        let data = body_to_string(body, &ContentType::Form, &Scrubber::default());
+
+        assert_eq!(data, "user=a&password=[Filtered]");
+    }
+
+    #[test]
+    fn binary_body() {
+        let body = b"\x89PNG\r\n";
+
+        let data = body_to_string(body, &ContentType::PNG, &Scrubber::default());
+
+        assert_eq!(data, "[Omitted: 6 bytes of image/png]");
+    }
+
+    #[test]
+    fn invalid_json_body() {
+        let body = b"{";
+
+        let data = body_to_string(body, &ContentType::JSON, &Scrubber::default());
+
+        assert_eq!(data, "[Omitted: 1 bytes of application/json]");
+    }
+}
@@ -0,0 +1,489 @@
+//! Sentry settings from the Rocket configuration.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use figment::providers::Serialized;
+use figment::value::{Dict, Value};
+use figment::Figment;
+use rocket::serde::de::DeserializeOwned;
+use rocket::serde::Deserialize;
+
+use crate::body::MAX_REQUEST_BODY_SIZE;
+use crate::{
+    ConfigError, IgnoreConfig, ScrubConfig, StatusRange, TracePropagation, TracesSampleRule,
+    TrustedProxy,
+};
+
+/// Key of the table holding the Sentry settings.
+const TABLE: &str = "sentry";
+/// Prefix of the top-level Sentry settings, the way they were configured before the table.
+const FLAT_PREFIX: &str = "sentry_";
+/// The environment variables that other Sentry SDKs read their settings from.
+const DSN_ENV: &str = "SENTRY_DSN";
+const ENVIRONMENT_ENV: &str = "SENTRY_ENVIRONMENT";
+const RELEASE_ENV: &str = "SENTRY_RELEASE";
+
+/// Sentry settings, configured per profile in a `sentry` table of `Rocket.toml`:
+///
+/// ```toml
+/// [release.sentry]
+/// dsn = "https://057006d7dfe5fff0fbed461cfca5f757@sentry.io/1111111"
+/// traces_sample_rate = 0.2
+/// release = "my-app@1.0.0"
+/// tags = { region = "eu-west-1" }
+/// ```
+///
+/// Each setting can also be given as a top-level key prefixed by `sentry_`, such as `sentry_dsn`
+/// or the `ROCKET_SENTRY_DSN` environment variable; the `sentry` table takes precedence over
+/// those. Unset settings fall back to the ones given to the [`RocketSentryBuilder`], then to the
+/// standard `SENTRY_DSN`, `SENTRY_ENVIRONMENT` and `SENTRY_RELEASE` environment variables, then
+/// to the defaults of the Sentry SDK.
+///
+/// [`RocketSentryBuilder`]: crate::RocketSentryBuilder
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SentryConfig {
+    /// Where to send events, Sentry stays disabled if this is empty.
+    pub dsn: Option<String>,
+    /// A file to read the DSN from if it isn't set otherwise, such as a mounted secret.
+    pub dsn_file: Option<PathBuf>,
+    /// See [`RocketSentryBuilder::required`](crate::RocketSentryBuilder::required).
+    pub required: Option<bool>,
+    /// The release the events belong to, for example `my-app@1.0.0`.
+    pub release: Option<String>,
+    /// The environment the events belong to, which overrides the one derived from the Rocket
+    /// profile.
+    pub environment: Option<String>,
+    /// Environments by Rocket profile name, see
+    /// [`RocketSentryBuilder::profile_environment`](crate::RocketSentryBuilder::profile_environment).
+    pub environments: Option<BTreeMap<String, String>>,
+    /// The name of the server, defaults to the hostname.
+    pub server_name: Option<String>,
+    /// Share of error events to send, between 0 and 1. Defaults to 1.
+    pub sample_rate: Option<f32>,
+    /// Share of transactions to send, between 0 and 1. Defaults to 0, disabling transactions.
+    pub traces_sample_rate: Option<f32>,
+    /// See [`TracesSampleRule`].
+    pub traces_sample_rules: Option<Vec<TracesSampleRule>>,
+    /// Attach stack traces to message events.
+    pub attach_stacktrace: Option<bool>,
+    /// Log the Sentry SDK's own debug messages.
+    pub debug: Option<bool>,
+    /// How many breadcrumbs to keep, defaults to 100.
+    pub max_breadcrumbs: Option<usize>,
+    /// See [`RocketSentryBuilder::send_default_pii`](crate::RocketSentryBuilder::send_default_pii).
+    pub send_default_pii: Option<bool>,
+    /// Proxy for sending events to a DSN with the `http` scheme.
+    pub http_proxy: Option<String>,
+    /// Proxy for sending events to a DSN with the `https` scheme.
+    pub https_proxy: Option<String>,
+    /// How long to wait for queued events to be sent on shutdown, in seconds. Defaults to 2.
+    pub shutdown_timeout: Option<f64>,
+    /// Module prefixes that belong to the application, for grouping stack frames.
+    pub in_app_include: Option<Vec<String>>,
+    /// Module prefixes that don't belong to the application, for grouping stack frames.
+    pub in_app_exclude: Option<Vec<String>>,
+    /// Tags set on all events and transactions.
+    pub tags: Option<BTreeMap<String, String>>,
+    /// See [`RocketSentryBuilder::trace_propagation`](crate::RocketSentryBuilder::trace_propagation).
+    pub trace_propagation: Option<TracePropagation>,
+    /// See [`RocketSentryBuilder::trace_response_headers`](crate::RocketSentryBuilder::trace_response_headers).
+    pub trace_response_headers: Option<bool>,
+    /// See [`RocketSentryBuilder::capture_error_responses`](crate::RocketSentryBuilder::capture_error_responses).
+    pub capture_error_responses: Option<bool>,
+    /// See [`RocketSentryBuilder::error_response_status`](crate::RocketSentryBuilder::error_response_status).
+    pub error_response_status: Option<Vec<StatusRange>>,
+    /// See [`ScrubConfig`].
+    pub scrub: Option<ScrubConfig>,
+    /// See [`IgnoreConfig`].
+    pub ignore: Option<IgnoreConfig>,
+    /// See [`RocketSentryBuilder::trusted_proxies`](crate::RocketSentryBuilder::trusted_proxies).
+    pub trusted_proxies: Option<Vec<TrustedProxy>>,
+    /// See [`RocketSentryBuilder::max_request_body_size`](crate::RocketSentryBuilder::max_request_body_size).
+    pub max_request_body_size: Option<usize>,
+    /// See [`RocketSentryBuilder::transaction_request_bodies`](crate::RocketSentryBuilder::transaction_request_bodies).
+    pub transaction_request_bodies: Option<bool>,
+}
+
+impl SentryConfig {
+    /// Extracts the Sentry settings of the selected profile, from both the `sentry` table and the
+    /// `sentry_`-prefixed keys.
+    ///
+    /// ```
+    /// use rocket_sentry::SentryConfig;
+    ///
+    /// let figment = rocket::Config::figment()
+    ///     .merge(("sentry_dsn", "https://123@sentry.io/456"))
+    ///     .merge(("sentry.release", "my-app@1.0.0"));
+    ///
+    /// let config = SentryConfig::from_figment(&figment).unwrap();
+    ///
+    /// assert_eq!(config.dsn.as_deref(), Some("https://123@sentry.io/456"));
+    /// assert_eq!(config.release.as_deref(), Some("my-app@1.0.0"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If a setting has an invalid value.
+    #[allow(clippy::result_large_err)] // Like `Figment::extract()`
+    pub fn from_figment(figment: &Figment) -> figment::Result<SentryConfig> {
+        extract(figment)
+    }
+
+    /// Whether Sentry is [required](crate::RocketSentryBuilder::required), even if other
+    /// settings are invalid.
+    pub(crate) fn required_from_figment(figment: &Figment) -> Option<bool> {
+        #[derive(Deserialize)]
+        #[serde(crate = "rocket::serde")]
+        struct Required {
+            required: Option<bool>,
+        }
+
+        extract::<Required>(figment).ok()?.required
+    }
+
+    /// Checks the settings that the Sentry SDK would otherwise silently clamp or ignore.
+    ///
+    /// # Errors
+    ///
+    /// If a sample rate isn't between 0 and 1, or the shutdown timeout isn't a positive number.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let sample_rates = [
+            ("sentry_sample_rate", self.sample_rate),
+            ("sentry_traces_sample_rate", self.traces_sample_rate),
+        ];
+        let rule_rates = self.traces_sample_rules.iter().flatten();
+        let rule_rates = rule_rates.map(|rule| ("sentry_traces_sample_rules", Some(rule.rate)));
+        for (setting, value) in sample_rates.into_iter().chain(rule_rates) {
+            match value {
+                Some(value) if !(0.0..=1.0).contains(&value) => {
+                    return Err(ConfigError::SampleRate { setting, value })
+                }
+                _ => {}
+            }
+        }
+        match self.max_request_body_size {
+            Some(size) if size > MAX_REQUEST_BODY_SIZE => {
+                return Err(ConfigError::MaxRequestBodySize(size))
+            }
+            _ => {}
+        }
+        match self.shutdown_timeout {
+            Some(timeout) if Duration::try_from_secs_f64(timeout).is_err() => {
+                Err(ConfigError::ShutdownTimeout(timeout))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The settings from the standard environment variables of Sentry SDKs.
+    pub(crate) fn from_env() -> SentryConfig {
+        SentryConfig::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> SentryConfig {
+        SentryConfig {
+            dsn: var(DSN_ENV),
+            environment: var(ENVIRONMENT_ENV),
+            release: var(RELEASE_ENV),
+            ..Default::default()
+        }
+    }
+
+    /// Reads the DSN from the [`dsn_file`](Self::dsn_file), unless the DSN is set.
+    pub(crate) fn read_dsn_file(&mut self) -> io::Result<()> {
+        if let (None, Some(path)) = (&self.dsn, &self.dsn_file) {
+            self.dsn = Some(read_dsn_file(path)?);
+        }
+        Ok(())
+    }
+
+    /// Fills in settings that are unset with the ones from `defaults`. The profile environments
+    /// are merged, preferring the mappings of `self` for the same profile.
+    #[must_use]
+    pub(crate) fn or(self, defaults: SentryConfig) -> SentryConfig {
+        SentryConfig {
+            dsn: self.dsn.or(defaults.dsn),
+            dsn_file: self.dsn_file.or(defaults.dsn_file),
+            required: self.required.or(defaults.required),
+            release: self.release.or(defaults.release),
+            environment: self.environment.or(defaults.environment),
+            environments: match (self.environments, defaults.environments) {
+                (Some(environments), Some(mut defaults)) => {
+                    defaults.extend(environments);
+                    Some(defaults)
+                }
+                (environments, defaults) => environments.or(defaults),
+            },
+            server_name: self.server_name.or(defaults.server_name),
+            sample_rate: self.sample_rate.or(defaults.sample_rate),
+            traces_sample_rate: self.traces_sample_rate.or(defaults.traces_sample_rate),
+            traces_sample_rules: self.traces_sample_rules.or(defaults.traces_sample_rules),
+            attach_stacktrace: self.attach_stacktrace.or(defaults.attach_stacktrace),
+            debug: self.debug.or(defaults.debug),
+            max_breadcrumbs: self.max_breadcrumbs.or(defaults.max_breadcrumbs),
+            send_default_pii: self.send_default_pii.or(defaults.send_default_pii),
+            http_proxy: self.http_proxy.or(defaults.http_proxy),
+            https_proxy: self.https_proxy.or(defaults.https_proxy),
+            shutdown_timeout: self.shutdown_timeout.or(defaults.shutdown_timeout),
+            in_app_include: self.in_app_include.or(defaults.in_app_include),
+            in_app_exclude: self.in_app_exclude.or(defaults.in_app_exclude),
+            tags: self.tags.or(defaults.tags),
+            trace_propagation: self.trace_propagation.or(defaults.trace_propagation),
+            trace_response_headers: self
+                .trace_response_headers
+                .or(defaults.trace_response_headers),
+            capture_error_responses: self
+                .capture_error_responses
+                .or(defaults.capture_error_responses),
+            error_response_status: self
+                .error_response_status
+                .or(defaults.error_response_status),
+            scrub: self.scrub.or(defaults.scrub),
+            ignore: self.ignore.or(defaults.ignore),
+            trusted_proxies: self.trusted_proxies.or(defaults.trusted_proxies),
+            max_request_body_size: self
+                .max_request_body_size
+                .or(defaults.max_request_body_size),
+            transaction_request_bodies: self
+                .transaction_request_bodies
+                .or(defaults.transaction_request_bodies),
+        }
+    }
+}
+
+/// Extracts settings from both the `sentry` table and the `sentry_`-prefixed keys.
+#[allow(clippy::result_large_err)] // Like `Figment::extract()`
+fn extract<T: DeserializeOwned>(figment: &Figment) -> figment::Result<T> {
+    let mut flat = Dict::new();
+    let mut table = Dict::new();
+    for (key, value) in figment.extract::<Dict>()? {
+        if key == TABLE {
+            if let Value::Dict(_, dict) = value {
+                table = dict;
+            }
+        } else if let Some(key) = key.strip_prefix(FLAT_PREFIX) {
+            flat.insert(key.to_string(), value);
+        }
+    }
+    let in_table: Vec<String> = table.keys().cloned().collect();
+    Figment::from(Serialized::defaults(flat))
+        .merge(Serialized::defaults(table))
+        .extract()
+        .map_err(|mut err| {
+            // Point the error at the key as it was configured
+            if let Some(key) = err.path.first_mut() {
+                if in_table.contains(key) {
+                    err.path.insert(0, TABLE.to_string());
+                } else {
+                    key.insert_str(0, FLAT_PREFIX);
+                }
+                err.metadata = figment.find_metadata(&err.path.join(".")).cloned();
+                err.profile = Some(figment.profile().clone());
+            }
+            err
+        })
+}
+
+fn read_dsn_file(path: &Path) -> io::Result<String> {
+    let dsn = std::fs::read_to_string(path)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+    // Secrets often end with a newline
+    let dsn = dsn.trim();
+    if dsn.is_empty() {
+        // Unlike an empty `sentry_dsn`, this is more likely a missing secret than a choice
+        let message = format!("{}: the file is empty", path.display());
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    Ok(dsn.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io;
+
+    use figment::Figment;
+
+    use super::SentryConfig;
+    use crate::{ConfigError, TracesSampleRule};
+
+    #[test]
+    fn table_overrides_flat_keys() {
+        let figment = Figment::new()
+            .merge(("sentry_dsn", "https://123@sentry.io/456"))
+            .merge(("sentry_debug", true))
+            .merge(("sentry.debug", false))
+            .merge(("sentry.tags", BTreeMap::from([("region", "eu")])))
+            .merge(("sentry.scrub.allow_cookies", ["locale"]));
+
+        let config = SentryConfig::from_figment(&figment).unwrap();
+
+        assert_eq!(config.dsn.as_deref(), Some("https://123@sentry.io/456"));
+        assert_eq!(config.debug, Some(false));
+        assert_eq!(config.tags.unwrap()["region"], "eu");
+        assert_eq!(config.scrub.unwrap().allow_cookies, vec!["locale"]);
+    }
+
+    #[test]
+    fn invalid_setting() {
+        let figment = Figment::new().merge(("sentry.max_breadcrumbs", "many"));
+
+        let err = SentryConfig::from_figment(&figment).unwrap_err();
+
+        assert_eq!(err.path, vec!["sentry", "max_breadcrumbs"]);
+        assert!(err.metadata.is_some());
+    }
+
+    #[test]
+    fn or_defaults() {
+        let environments = |pairs: &[(&str, &str)]| {
+            let pairs = pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string()));
+            Some(pairs.collect::<BTreeMap<_, _>>())
+        };
+        let config = SentryConfig {
+            release: Some("a".to_string()),
+            environments: environments(&[("release", "staging")]),
+            ..Default::default()
+        };
+        let defaults = SentryConfig {
+            release: Some("b".to_string()),
+            debug: Some(true),
+            environments: environments(&[("release", "production"), ("qa", "testing")]),
+            ..Default::default()
+        };
+
+        let config = config.or(defaults);
+
+        assert_eq!(config.release.as_deref(), Some("a"));
+        assert_eq!(config.debug, Some(true));
+        assert_eq!(
+            config.environments,
+            environments(&[("release", "staging"), ("qa", "testing")])
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let config = SentryConfig {
+            sample_rate: Some(1.0),
+            traces_sample_rate: Some(-0.1),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "`sentry_traces_sample_rate` must be between 0 and 1, not -0.1"
+        );
+
+        let config = SentryConfig {
+            traces_sample_rules: Some(vec![TracesSampleRule {
+                method: None,
+                path: Some("/healthz".to_string()),
+                rate: 2.0,
+                override_parent: false,
+            }]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::SampleRate {
+                setting: "sentry_traces_sample_rules",
+                ..
+            })
+        ));
+
+        let config = SentryConfig {
+            shutdown_timeout: Some(f64::NAN),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::ShutdownTimeout(_))
+        ));
+
+        let config = SentryConfig {
+            max_request_body_size: Some(4096),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "`sentry_max_request_body_size` can be at most 512 bytes, not 4096"
+        );
+    }
+
+    #[test]
+    fn required_from_figment() {
+        let figment = Figment::new()
+            .merge(("sentry_required", true))
+            .merge(("sentry_max_breadcrumbs", "many"));
+
+        assert_eq!(SentryConfig::required_from_figment(&figment), Some(true));
+    }
+
+    #[test]
+    fn read_dsn_file() {
+        let path = std::env::temp_dir().join("rocket-sentry-read-dsn-file");
+        std::fs::write(&path, "https://123@sentry.io/456\n").unwrap();
+        let mut config = SentryConfig {
+            dsn_file: Some(path.clone()),
+            ..Default::default()
+        };
+
+        config.read_dsn_file().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.dsn.as_deref(), Some("https://123@sentry.io/456"));
+    }
+
+    #[test]
+    fn read_missing_dsn_file() {
+        let mut config = SentryConfig {
+            dsn_file: Some("/nonexistent/sentry-dsn".into()),
+            ..Default::default()
+        };
+
+        let err = config.read_dsn_file().unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().starts_with("/nonexistent/sentry-dsn: "));
+        assert_eq!(config.dsn, None);
+    }
+
+    #[test]
+    fn read_empty_dsn_file() {
+        let path = std::env::temp_dir().join("rocket-sentry-read-empty-dsn-file");
+        std::fs::write(&path, " \n").unwrap();
+        let mut config = SentryConfig {
+            dsn_file: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let err = config.read_dsn_file().unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .ends_with("rocket-sentry-read-empty-dsn-file: the file is empty"));
+        assert_eq!(config.dsn, None);
+    }
+
+    #[test]
+    fn from_vars() {
+        let vars = BTreeMap::from([
+            ("SENTRY_DSN", "https://123@sentry.io/456"),
+            ("SENTRY_RELEASE", "1.0"),
+            ("SENTRY_TRACES_SAMPLE_RATE", "1.0"),
+        ]);
+
+        let config = SentryConfig::from_vars(|name| vars.get(name).map(ToString::to_string));
+
+        assert_eq!(config.dsn.as_deref(), Some("https://123@sentry.io/456"));
+        assert_eq!(config.release.as_deref(), Some("1.0"));
+        assert_eq!(config.environment, None);
+        assert_eq!(config.traces_sample_rate, None); // Not a standard variable
+    }
+}
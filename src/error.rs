@@ -0,0 +1,94 @@
+//! Errors in the Sentry configuration.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use sentry::types::ParseDsnError;
+
+use crate::body::MAX_REQUEST_BODY_SIZE;
+
+/// Why Sentry could not be set up from the Rocket configuration.
+///
+/// These are logged, and with [`required`](crate::RocketSentryBuilder::required) they also abort
+/// the launch.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// A setting has a value of the wrong type.
+    Setting(Box<figment::Error>),
+    /// The `sentry_dsn_file` could not be read.
+    DsnFile(io::Error),
+    /// No DSN is configured, or it is empty.
+    MissingDsn,
+    /// The DSN could not be parsed.
+    Dsn(ParseDsnError),
+    /// A sample rate isn't between 0 and 1.
+    SampleRate { setting: &'static str, value: f32 },
+    /// The shutdown timeout isn't a positive number of seconds.
+    ShutdownTimeout(f64),
+    /// The request body size is above what Rocket lets the fairing peek at.
+    MaxRequestBodySize(usize),
+    /// A scrubbing rule isn't a valid regular expression.
+    Scrub(regex::Error),
+    /// An ignore rule isn't a valid regular expression or glob.
+    Ignore(regex::Error),
+    /// A traces sample rule has an invalid path glob.
+    TracesSampleRules(regex::Error),
+    /// The Sentry client ended up disabled.
+    NotInitialized,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Setting(err) => write!(f, "{err}"),
+            ConfigError::DsnFile(err) => write!(f, "DSN file could not be read: {err}"),
+            ConfigError::MissingDsn => write!(f, "missing `sentry_dsn`"),
+            ConfigError::Dsn(err) => write!(f, "DSN is invalid: {err}"),
+            ConfigError::SampleRate { setting, value } => {
+                write!(f, "`{setting}` must be between 0 and 1, not {value}")
+            }
+            ConfigError::ShutdownTimeout(value) => write!(
+                f,
+                "`sentry_shutdown_timeout` must be a positive number of seconds, not {value}"
+            ),
+            ConfigError::MaxRequestBodySize(value) => write!(
+                f,
+                "`sentry_max_request_body_size` can be at most {MAX_REQUEST_BODY_SIZE} bytes, not {value}"
+            ),
+            ConfigError::Scrub(err) => write!(f, "scrubbing rules are invalid: {err}"),
+            ConfigError::Ignore(err) => write!(f, "ignore rules are invalid: {err}"),
+            ConfigError::TracesSampleRules(err) => {
+                write!(f, "traces sample rules are invalid: {err}")
+            }
+            ConfigError::NotInitialized => write!(f, "the Sentry client did not initialize"),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Setting(err) => Some(&**err),
+            ConfigError::DsnFile(err) => Some(err),
+            ConfigError::Dsn(err) => Some(err),
+            ConfigError::Scrub(err)
+            | ConfigError::Ignore(err)
+            | ConfigError::TracesSampleRules(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<figment::Error> for ConfigError {
+    fn from(err: figment::Error) -> Self {
+        ConfigError::Setting(Box::new(err))
+    }
+}
+
+impl From<ParseDsnError> for ConfigError {
+    fn from(err: ParseDsnError) -> Self {
+        ConfigError::Dsn(err)
+    }
+}
@@ -0,0 +1,163 @@
+//! Access to the fairing's Sentry client from anywhere in the application.
+
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use rocket::Request;
+use sentry::types::Uuid;
+use sentry::{Hub, Level};
+
+use crate::get_request_hub;
+
+/// A handle to the Sentry client of the fairing, which puts it in Rocket's managed state on
+/// ignite, also when Sentry is disabled.
+///
+/// Handlers can get it as a `&State<SentryHandle>` request guard:
+///
+/// ```no_run
+/// # #[macro_use]
+/// # extern crate rocket;
+/// use rocket::State;
+/// use rocket_sentry::SentryHandle;
+///
+/// #[get("/import")]
+/// fn import(sentry: &State<SentryHandle>) -> &'static str {
+///     if let Err(err) = "x".parse::<u32>() {
+///         sentry.capture_error(&err);
+///     }
+///     "Imported"
+/// }
+/// ```
+///
+/// And liftoff code from the launched Rocket, with `rocket.state::<SentryHandle>()`.
+///
+/// Events captured through the handle include the request data when captured in handlers that
+/// are wrapped with [`bind_hub`](crate::bind_hub). Elsewhere, such as in catchers and request
+/// guards, use the hub of the request with [`request_hub`](Self::request_hub).
+#[derive(Clone, Default)]
+pub struct SentryHandle(Arc<Shared>);
+
+#[derive(Default)]
+struct Shared {
+    /// The hub Sentry was initialized on, which per-request hubs are forked from.
+    hub: Mutex<Option<Arc<Hub>>>,
+    requests: AtomicU64,
+    transactions: AtomicU64,
+    error_responses: AtomicU64,
+    events: AtomicU64,
+}
+
+/// Counters of what the fairing did since launch, see [`SentryHandle::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SentryStats {
+    /// Requests handled while Sentry was enabled.
+    pub requests: u64,
+    /// Transactions started for requests, whether they are sampled or not.
+    pub transactions: u64,
+    /// Events captured for responses with an error status.
+    pub error_responses: u64,
+    /// Events handed over to the transport, after sampling and `before_send`.
+    pub events: u64,
+}
+
+impl SentryHandle {
+    /// Whether Sentry was initialized.
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.hub()
+            .and_then(|hub| hub.client())
+            .is_some_and(|client| client.is_enabled())
+    }
+
+    /// The hub Sentry was initialized on, for capturing events outside of requests.
+    #[must_use]
+    pub fn hub(&self) -> Option<Arc<Hub>> {
+        self.0
+            .hub
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The hub of a request, whose events include the request data, or else the hub Sentry was
+    /// initialized on.
+    #[must_use]
+    pub fn request_hub(&self, request: &Request<'_>) -> Option<Arc<Hub>> {
+        get_request_hub(request).cloned().or_else(|| self.hub())
+    }
+
+    /// Captures a message, see [above](Self) for which request it belongs to.
+    #[allow(clippy::must_use_candidate)] // The event ID is rarely needed
+    pub fn capture_message(&self, message: &str, level: Level) -> Uuid {
+        match self.current_hub() {
+            Some(hub) => hub.capture_message(message, level),
+            None => Uuid::nil(),
+        }
+    }
+
+    /// Captures an error, see [above](Self) for which request it belongs to.
+    pub fn capture_error<E: Error + ?Sized>(&self, error: &E) -> Uuid {
+        match self.current_hub() {
+            Some(hub) => hub.capture_error(error),
+            None => Uuid::nil(),
+        }
+    }
+
+    /// Waits for at most `timeout` until queued events and transactions are sent.
+    ///
+    /// Returns `false` if they were not all sent in time, or if Sentry is disabled.
+    pub async fn flush(&self, timeout: Duration) -> bool {
+        let Some(client) = self.hub().and_then(|hub| hub.client()) else {
+            return false;
+        };
+        let flush = move || client.flush(Some(timeout));
+        rocket::tokio::task::spawn_blocking(flush)
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Counters of what the fairing did since launch.
+    #[must_use]
+    pub fn stats(&self) -> SentryStats {
+        SentryStats {
+            requests: self.0.requests.load(Ordering::Relaxed),
+            transactions: self.0.transactions.load(Ordering::Relaxed),
+            error_responses: self.0.error_responses.load(Ordering::Relaxed),
+            events: self.0.events.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The request's hub within handlers wrapped with `bind_hub`, unless the thread's hub is
+    /// missing the client, such as when Sentry was initialized on another thread.
+    fn current_hub(&self) -> Option<Arc<Hub>> {
+        let hub = Hub::current();
+        if hub.client().is_some() {
+            Some(hub)
+        } else {
+            self.hub()
+        }
+    }
+
+    pub(crate) fn set_hub(&self, hub: Arc<Hub>) {
+        *self.0.hub.lock().unwrap_or_else(PoisonError::into_inner) = Some(hub);
+    }
+
+    pub(crate) fn count_request(&self) {
+        self.0.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_transaction(&self) {
+        self.0.transactions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_error_response(&self) {
+        self.0.error_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_event(&self) {
+        self.0.events.fetch_add(1, Ordering::Relaxed);
+    }
+}
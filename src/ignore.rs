@@ -0,0 +1,209 @@
+//! Requests that don't get a transaction, such as health checks and static assets.
+
+use std::collections::HashSet;
+
+use regex::{RegexSet, RegexSetBuilder};
+use rocket::serde::Deserialize;
+use rocket::{Request, Route};
+
+/// Rules for requests that don't get a transaction, matching any of them is enough.
+///
+/// - `paths` are globs of request paths, where `*` matches within a path segment and `**`
+///   across segments, such as `/assets/**`. Rocket's dynamic segments match as well, such as
+///   `/users/<id>/avatar`.
+/// - `methods` are HTTP methods, such as `OPTIONS`.
+/// - `mounts` are the base paths routes are mounted at; routes mounted at or below one match.
+/// - `routes` are route names, the name of the handler function by default.
+/// - `user_agents` are regular expressions matching anywhere in the `User-Agent` header,
+///   case-insensitively.
+///
+/// Mounts and routes are only known after routing, so these transactions are started but never
+/// sent.
+///
+/// Configured with a `sentry.ignore` table in `Rocket.toml`, for example:
+///
+/// ```toml
+/// [default.sentry.ignore]
+/// paths = ["/healthz", "/assets/**"]
+/// methods = ["OPTIONS", "HEAD"]
+/// mounts = ["/static"]
+/// routes = ["metrics"]
+/// user_agents = ["bot", "crawler", "spider"]
+/// ```
+///
+/// Events, such as for [error responses](crate::RocketSentryBuilder::capture_error_responses),
+/// are still captured for these requests.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct IgnoreConfig {
+    pub paths: Vec<String>,
+    pub methods: Vec<String>,
+    pub mounts: Vec<String>,
+    pub routes: Vec<String>,
+    pub user_agents: Vec<String>,
+}
+
+/// Compiled [`IgnoreConfig`].
+pub(crate) struct IgnoreRules {
+    paths: RegexSet,
+    methods: Vec<String>,
+    mounts: Vec<String>,
+    routes: HashSet<String>,
+    user_agents: RegexSet,
+}
+
+impl IgnoreRules {
+    pub(crate) fn new(config: &IgnoreConfig) -> Result<Self, regex::Error> {
+        Ok(IgnoreRules {
+            paths: RegexSet::new(config.paths.iter().map(|glob| glob_to_regex(glob)))?,
+            methods: config.methods.clone(),
+            mounts: config
+                .mounts
+                .iter()
+                .map(|mount| mount.trim_end_matches('/').to_string())
+                .collect(),
+            routes: config.routes.iter().cloned().collect(),
+            user_agents: RegexSetBuilder::new(&config.user_agents)
+                .case_insensitive(true)
+                .build()?,
+        })
+    }
+
+    /// Whether the request matches a rule that is known before routing.
+    pub(crate) fn ignores_request(&self, request: &Request) -> bool {
+        let path = request.uri().path().as_str();
+        let method = request.method();
+        self.paths.is_match(path)
+            || self
+                .methods
+                .iter()
+                .any(|ignored| ignored.eq_ignore_ascii_case(method.as_str()))
+            || request
+                .headers()
+                .get_one("User-Agent")
+                .is_some_and(|user_agent| self.user_agents.is_match(user_agent))
+    }
+
+    /// Whether the matched route is ignored by name or mount point.
+    pub(crate) fn ignores_route(&self, route: &Route) -> bool {
+        route
+            .name
+            .as_ref()
+            .is_some_and(|name| self.routes.contains(name.as_ref()))
+            || self
+                .mounts
+                .iter()
+                .any(|mount| is_below(route.uri.base(), mount))
+    }
+}
+
+/// Translates a path glob to a regular expression, also accepting Rocket's dynamic segments,
+/// such as `/users/<id>` and `/files/<path..>`.
+pub(crate) fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '<' => {
+                let param: String = chars.by_ref().take_while(|&char| char != '>').collect();
+                if param.ends_with("..") {
+                    regex.push_str(".*");
+                } else {
+                    regex.push_str("[^/]+");
+                }
+            }
+            char => regex.push_str(&regex::escape(&char.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Whether the base path is the mount point or below it, with the mount's trailing `/` removed.
+fn is_below(path: &str, mount: &str) -> bool {
+    match path.strip_prefix(mount) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{Header, Method};
+    use rocket::local::asynchronous::Client;
+
+    use super::{IgnoreConfig, IgnoreRules};
+
+    fn rules() -> IgnoreRules {
+        IgnoreRules::new(&IgnoreConfig {
+            paths: vec![
+                "/healthz".to_string(),
+                "/assets/**/*.js".to_string(),
+                "/users/<id>/avatar".to_string(),
+            ],
+            methods: vec!["options".to_string()],
+            mounts: vec!["/static/".to_string()],
+            routes: vec!["metrics".to_string()],
+            user_agents: vec!["bot".to_string()],
+        })
+        .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn ignores_request() {
+        let rules = rules();
+        let client = Client::untracked(rocket::build()).await.unwrap();
+        let ignored = |method: Method, uri: &'static str, user_agent: &'static str| {
+            let request = client
+                .req(method, uri)
+                .header(Header::new("User-Agent", user_agent));
+            rules.ignores_request(request.inner())
+        };
+
+        assert!(ignored(Method::Get, "/healthz", "curl"));
+        assert!(!ignored(Method::Get, "/healthz/deep", "curl"));
+        assert!(ignored(Method::Get, "/assets/js/app.js", "curl"));
+        assert!(!ignored(Method::Get, "/assets/app.css", "curl"));
+        assert!(ignored(Method::Get, "/users/42/avatar", "curl"));
+        assert!(!ignored(Method::Get, "/users//avatar", "curl"));
+        assert!(ignored(Method::Options, "/users", "curl"));
+        assert!(!ignored(Method::Get, "/static/logo.png", "curl"));
+        assert!(ignored(Method::Get, "/users", "Googlebot/2.1"));
+        assert!(!ignored(Method::Get, "/users", "curl"));
+    }
+
+    #[rocket::get("/metrics")]
+    fn metrics() {}
+
+    #[rocket::get("/users")]
+    fn users() {}
+
+    #[test]
+    fn ignores_route() {
+        let rules = rules();
+        let routes = rocket::routes![metrics, users];
+        let mounted = |base: &str| routes[1].clone().map_base(|_| base.to_string()).unwrap();
+
+        assert!(rules.ignores_route(&routes[0]));
+        assert!(!rules.ignores_route(&routes[1]));
+        assert!(rules.ignores_route(&mounted("/static")));
+        assert!(rules.ignores_route(&mounted("/static/v2")));
+        assert!(!rules.ignores_route(&mounted("/staticfiles")));
+    }
+
+    #[test]
+    fn invalid_user_agent_pattern() {
+        let config = IgnoreConfig {
+            user_agents: vec!["(".to_string()],
+            ..Default::default()
+        };
+
+        assert!(IgnoreRules::new(&config).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Transactions of requests in flight, which are aborted if Rocket shuts down before they respond.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rocket::Request;
+use sentry::protocol::{self, SpanId, SpanStatus};
+use sentry::{Hub, Transaction};
+
+use crate::before_send::{self, TransactionOrigin};
+use crate::transaction::TransactionOverrides;
+
+/// How often shutdown checks whether the requests in flight are done.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What finishing a transaction takes, for when its request doesn't get to respond.
+pub(crate) struct InFlight {
+    pub(crate) transaction: Transaction,
+    pub(crate) hub: Arc<Hub>,
+    pub(crate) request: protocol::Request,
+    pub(crate) overrides: Arc<TransactionOverrides>,
+    pub(crate) origin: TransactionOrigin,
+}
+
+impl InFlight {
+    /// Finishes the transaction like a response would, except with an aborted status.
+    fn abort(self) {
+        // Routes bound to the request hub have already named it after the matched route
+        self.transaction.set_status(SpanStatus::Aborted);
+        self.overrides.apply(&self.transaction);
+        self.transaction.set_request(self.request);
+        let transaction = self.transaction;
+        before_send::with_transaction_origin(self.origin, || {
+            Hub::run(self.hub, || transaction.finish());
+        });
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct InFlightRequests(Arc<Mutex<HashMap<SpanId, InFlight>>>);
+
+/// Forgets a request once Rocket drops it, which is without a response if the client
+/// disconnected.
+struct Entry {
+    requests: InFlightRequests,
+    span_id: SpanId,
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        self.requests.remove(self.span_id);
+    }
+}
+
+impl InFlightRequests {
+    pub(crate) fn insert(&self, request: &Request, in_flight: InFlight) {
+        let span_id = in_flight.transaction.get_trace_context().span_id;
+        self.0.lock().unwrap().insert(span_id, in_flight);
+        request.local_cache(|| Entry {
+            requests: self.clone(),
+            span_id,
+        });
+    }
+
+    pub(crate) fn remove(&self, span_id: SpanId) {
+        self.0.lock().unwrap().remove(&span_id);
+    }
+
+    /// Waits up to `timeout` for the requests in flight to respond, then aborts the transactions
+    /// of the others.
+    pub(crate) async fn abort(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while !self.0.lock().unwrap().is_empty() && Instant::now() < deadline {
+            rocket::tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        let in_flight: Vec<_> = self.0.lock().unwrap().drain().collect();
+        for (_, in_flight) in in_flight {
+            in_flight.abort();
+        }
+    }
+}
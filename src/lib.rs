@@ -45,30 +45,211 @@
 extern crate log;
 
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::ops::RangeInclusive;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
+use figment::{Figment, Profile};
 use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Status;
 use rocket::request::local_cache_once;
-use rocket::serde::Deserialize;
-use rocket::{fairing, Build, Data, Request, Response, Rocket};
-use sentry::protocol::SpanStatus;
-use sentry::{protocol, ClientInitGuard, ClientOptions, Hub, TracesSampler, Transaction};
+use rocket::route::{self, Handler};
+use rocket::serde::de::{self, Visitor};
+use rocket::serde::{Deserialize, Deserializer};
+use rocket::{fairing, Build, Data, Orbit, Request, Response, Rocket, Route};
+use sentry::protocol::{IpAddress, SpanStatus};
+use sentry::types::ParseDsnError;
+use sentry::{
+    protocol, ClientInitGuard, ClientOptions, Hub, IntoDsn, Level, SentryFutureExt, TracesSampler,
+    Transaction, TransactionContext, User,
+};
+
+mod before_send;
+mod body;
+mod config;
+mod error;
+mod handle;
+mod ignore;
+mod in_flight;
+mod propagation;
+mod proxy;
+mod sampling;
+mod scrub;
+mod transaction;
+mod user;
+
+pub use crate::before_send::RequestMetadata;
+use crate::before_send::{
+    BeforeSend, BeforeSendTransaction, CachedRequestMetadata, TransactionOrigin,
+};
+use crate::body::RequestBody;
+pub use crate::config::SentryConfig;
+pub use crate::error::ConfigError;
+pub use crate::handle::{SentryHandle, SentryStats};
+pub use crate::ignore::IgnoreConfig;
+use crate::ignore::IgnoreRules;
+use crate::in_flight::{InFlight, InFlightRequests};
+pub use crate::propagation::TracePropagation;
+pub use crate::proxy::TrustedProxy;
+use crate::sampling::{RequestSampler, SampleRules};
+pub use crate::sampling::{SamplingDecision, TracesSampleRule};
+pub use crate::scrub::ScrubConfig;
+use crate::scrub::{Scrubber, FILTERED};
+pub use crate::transaction::SentryTransaction;
+use crate::transaction::TransactionOverrides;
+pub use crate::user::UserIdentifier;
 
 const TRANSACTION_OPERATION_NAME: &str = "http.server";
+/// Trace data key describing how the transaction name was derived.
+///
+/// Sentry reads this from `transaction_info.source`, see
+/// <https://develop.sentry.dev/sdk/event-payloads/properties/transaction_info/>, but sentry-rust
+/// has no way to set `transaction_info` yet. This trace data is the closest workaround, and it
+/// doesn't take the place of the source in Sentry's transaction grouping.
+const TRANSACTION_SOURCE_KEY: &str = "sentry.source";
+/// Stands in for the path of requests that didn't match a route, so that scans of unknown URLs
+/// don't each create their own transaction name.
+const UNMATCHED_PATH: &str = "<unmatched>";
+const HTTP_STATUS_CODE_TAG: &str = "http.status_code";
+const ROUTE_CONTEXT: &str = "route";
+const COOKIE_HEADER: &str = "Cookie";
+/// CGI-style environment keys, which Sentry also infers the user's IP address from
+const REMOTE_ADDR_ENV: &str = "REMOTE_ADDR";
+const HTTPS_ENV: &str = "HTTPS";
 
 pub struct RocketSentry {
     guard: Mutex<Option<ClientInitGuard>>,
+    /// Shared with the application through managed state.
+    handle: SentryHandle,
     transactions_enabled: AtomicBool,
+    /// Transactions of requests in flight, which are aborted if shutdown cuts them short.
+    in_flight: InFlightRequests,
     traces_sampler: Option<Arc<TracesSampler>>,
+    request_sampler: Option<Arc<RequestSampler>>,
+    user_identifier: Option<Arc<dyn UserIdentifier>>,
+    configure_client_options: Option<Box<ConfigureClientOptions>>,
+    before_send: Option<Arc<BeforeSend>>,
+    before_send_transaction: Option<Arc<BeforeSendTransaction>>,
+    /// Settings from the builder, which the Rocket configuration takes precedence over.
+    config: SentryConfig,
+    /// Set on ignite, so requests don't need to lock anything to read them.
+    settings: OnceLock<Settings>,
 }
 
-#[derive(Deserialize)]
-struct Config {
-    sentry_dsn: String,
-    sentry_traces_sample_rate: Option<f32>, // Default is 0 so no transaction transmitted
+/// Adjusts the client options before Sentry is initialized, see
+/// [`RocketSentryBuilder::client_options`].
+type ConfigureClientOptions = dyn Fn(&mut ClientOptions) + Send + Sync;
+
+/// Settings for handling requests, resolved from the [`SentryConfig`] on ignite.
+struct Settings {
+    trace_propagation: TracePropagation,
+    trace_response_headers: bool,
+    capture_error_responses: bool,
+    error_response_status: Vec<StatusRange>,
+    scrubber: Scrubber,
+    ignore: IgnoreRules,
+    traces_sample_rules: SampleRules,
+    trusted_proxies: Vec<TrustedProxy>,
+    max_request_body_size: usize,
+    transaction_request_bodies: bool,
+}
+
+impl Settings {
+    fn new(config: &SentryConfig) -> Result<Settings, ConfigError> {
+        let scrubber = Scrubber::new(
+            config.scrub.as_ref().unwrap_or(&ScrubConfig::default()),
+            config.send_default_pii.unwrap_or(false),
+        )
+        .map_err(ConfigError::Scrub)?;
+        let ignore = IgnoreRules::new(config.ignore.as_ref().unwrap_or(&IgnoreConfig::default()))
+            .map_err(ConfigError::Ignore)?;
+        let traces_sample_rules =
+            SampleRules::new(config.traces_sample_rules.as_deref().unwrap_or_default())
+                .map_err(ConfigError::TracesSampleRules)?;
+        Ok(Settings {
+            trace_propagation: config.trace_propagation.unwrap_or_default(),
+            trace_response_headers: config.trace_response_headers.unwrap_or(false),
+            capture_error_responses: config.capture_error_responses.unwrap_or(false),
+            error_response_status: config
+                .error_response_status
+                .clone()
+                .unwrap_or_else(|| vec![StatusRange::from(500..=599)]),
+            scrubber,
+            ignore,
+            traces_sample_rules,
+            trusted_proxies: config.trusted_proxies.clone().unwrap_or_default(),
+            max_request_body_size: config.max_request_body_size.unwrap_or(0),
+            transaction_request_bodies: config.transaction_request_bodies.unwrap_or(false),
+        })
+    }
+
+    fn is_error_response(&self, response: &Response) -> bool {
+        let status = response.status();
+        self.error_response_status
+            .iter()
+            .any(|range| range.contains(status))
+    }
+}
+
+/// An inclusive range of HTTP status codes.
+///
+/// In `Rocket.toml`, either a single code like `503` or a range like `"500-599"`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusRange(RangeInclusive<u16>);
+
+impl StatusRange {
+    fn contains(&self, status: Status) -> bool {
+        self.0.contains(&status.code)
+    }
+}
+
+impl From<RangeInclusive<u16>> for StatusRange {
+    fn from(range: RangeInclusive<u16>) -> Self {
+        StatusRange(range)
+    }
+}
+
+impl From<u16> for StatusRange {
+    fn from(code: u16) -> Self {
+        StatusRange(code..=code)
+    }
+}
+
+impl<'de> Deserialize<'de> for StatusRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StatusRangeVisitor;
+
+        impl Visitor<'_> for StatusRangeVisitor {
+            type Value = StatusRange;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a status code or a range of status codes like \"500-599\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, code: u64) -> Result<StatusRange, E> {
+                let code = u16::try_from(code).map_err(E::custom)?;
+                Ok(StatusRange::from(code))
+            }
+
+            fn visit_i64<E: de::Error>(self, code: i64) -> Result<StatusRange, E> {
+                let code = u16::try_from(code).map_err(E::custom)?;
+                Ok(StatusRange::from(code))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<StatusRange, E> {
+                let parse = |code: &str| code.trim().parse::<u16>().map_err(E::custom);
+                match value.split_once('-') {
+                    Some((start, end)) => Ok(StatusRange(parse(start)?..=parse(end)?)),
+                    None => Ok(StatusRange::from(parse(value)?)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(StatusRangeVisitor)
+    }
 }
 
 impl RocketSentry {
@@ -82,43 +263,180 @@ impl RocketSentry {
         RocketSentryBuilder::new()
     }
 
-    fn init(&self, dsn: &str, traces_sample_rate: f32, environment: Cow<'static, str>) {
-        let guard = sentry::init((
-            dsn,
-            ClientOptions {
-                before_send: Some(Arc::new(|event| {
-                    info!("Sending event to Sentry: {}", event.event_id);
-                    Some(event)
-                })),
+    /// Builds the Sentry client options from the Rocket configuration.
+    fn client_options(
+        &self,
+        config: &SentryConfig,
+        environment: Cow<'static, str>,
+    ) -> Result<ClientOptions, ParseDsnError> {
+        let defaults = ClientOptions::default();
+        let traces_sample_rate = config.traces_sample_rate.unwrap_or(0f32); // No transactions
+        let has_rules = config
+            .traces_sample_rules
+            .as_ref()
+            .is_some_and(|rules| !rules.is_empty());
+        // Rates chosen per request are handed over to Sentry through the transaction context
+        let traces_sampler = if has_rules || self.request_sampler.is_some() {
+            Some(sampling::traces_sampler(
+                self.traces_sampler.clone(),
                 traces_sample_rate,
-                traces_sampler: self.traces_sampler.clone(),
-                environment: Some(environment),
-                ..Default::default()
-            },
-        ));
+            ))
+        } else {
+            self.traces_sampler.clone()
+        };
+        let leak = |modules: &Option<Vec<String>>| -> Option<Vec<&'static str>> {
+            Some(
+                modules
+                    .as_ref()?
+                    .iter()
+                    .map(|module| leak_str(module))
+                    .collect(),
+            )
+        };
+        Ok(ClientOptions {
+            dsn: config.dsn.as_deref().into_dsn()?,
+            before_send: Some(before_send::chain_before_send(
+                self.before_send.clone(),
+                self.handle.clone(),
+            )),
+            release: config.release.clone().map(Cow::Owned),
+            environment: Some(environment),
+            server_name: config.server_name.clone().map(Cow::Owned),
+            sample_rate: config.sample_rate.unwrap_or(defaults.sample_rate),
+            traces_sample_rate,
+            traces_sampler,
+            attach_stacktrace: config
+                .attach_stacktrace
+                .unwrap_or(defaults.attach_stacktrace),
+            debug: config.debug.unwrap_or(defaults.debug),
+            max_breadcrumbs: config.max_breadcrumbs.unwrap_or(defaults.max_breadcrumbs),
+            send_default_pii: config.send_default_pii.unwrap_or(false),
+            http_proxy: config
+                .http_proxy
+                .clone()
+                .map(Cow::Owned)
+                .or(defaults.http_proxy),
+            https_proxy: config
+                .https_proxy
+                .clone()
+                .map(Cow::Owned)
+                .or(defaults.https_proxy),
+            shutdown_timeout: config
+                .shutdown_timeout
+                .and_then(|timeout| Duration::try_from_secs_f64(timeout).ok())
+                .unwrap_or(defaults.shutdown_timeout),
+            in_app_include: leak(&config.in_app_include).unwrap_or(defaults.in_app_include),
+            in_app_exclude: leak(&config.in_app_exclude).unwrap_or(defaults.in_app_exclude),
+            ..defaults
+        })
+    }
+
+    fn init(&self, options: ClientOptions) -> Result<(), ConfigError> {
+        let transactions_enabled =
+            options.traces_sample_rate > 0f32 || options.traces_sampler.is_some();
+        let guard = sentry::init(options);
 
         if guard.is_enabled() {
             // Tuck the ClientInitGuard in the fairing, so it lives as long as the server.
             let mut self_guard = self.guard.lock().unwrap();
             *self_guard = Some(guard);
+            self.handle.set_hub(Hub::current());
 
             info!("Sentry enabled.");
-            if traces_sample_rate > 0f32 || self.traces_sampler.is_some() {
+            if transactions_enabled {
                 self.transactions_enabled.store(true, Ordering::Relaxed);
             }
+            Ok(())
         } else {
-            error!("Sentry did not initialize.");
+            Err(ConfigError::NotInitialized)
         }
     }
 
-    fn start_transaction(name: &str) -> Transaction {
-        let transaction_context = sentry::TransactionContext::new(name, TRANSACTION_OPERATION_NAME);
-        let transaction = sentry::start_transaction(transaction_context);
-        Hub::current().configure_scope(|scope| {
-            scope.set_span(Some(transaction.clone().into()));
-        });
+    /// Initializes Sentry from the Rocket configuration.
+    fn configure(&self, figment: &Figment, required: bool) -> Result<(), ConfigError> {
+        let mut config = SentryConfig::from_figment(figment)?.or(self.config.clone());
+        // A DSN file configured for the application beats the generic environment variable
+        config.read_dsn_file().map_err(ConfigError::DsnFile)?;
+        // So does an environment mapped to the profile, over `SENTRY_ENVIRONMENT`
+        if config.environment.is_none() {
+            config.environment = profile_environment(&config, figment.profile());
+        }
+        let config = config.or(SentryConfig::from_env());
+        config.validate()?;
+        if self.settings.set(Settings::new(&config)?).is_err() {
+            warn!("Sentry was already configured by another launch, keeping its settings.");
+        }
+        match config.dsn.as_deref() {
+            None | Some("") if required => return Err(ConfigError::MissingDsn),
+            None => {
+                info!("Sentry disabled: no `sentry_dsn` or `SENTRY_DSN` configured.");
+                return Ok(());
+            }
+            Some("") => {
+                info!("Sentry disabled.");
+                return Ok(());
+            }
+            Some(_) => {}
+        }
+
+        let mut options = self.client_options(&config, environment(&config, figment.profile()))?;
+        if let Some(configure_client_options) = &self.configure_client_options {
+            configure_client_options(&mut options);
+        }
+        before_send::wrap_transport(&mut options, self.before_send_transaction.clone());
+        self.init(options)?;
+        if let (Some(hub), Some(tags)) = (self.handle.hub(), config.tags) {
+            hub.configure_scope(|scope| {
+                for (key, value) in tags {
+                    scope.set_tag(&key, value);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn start_transaction(hub: &Hub, transaction_context: TransactionContext) -> Transaction {
+        let transaction = hub.start_transaction(transaction_context);
+        let span = Some(transaction.clone().into());
+        hub.configure_scope(|scope| scope.set_span(span.clone()));
+        // Handlers not wrapped with `bind_hub` still find the transaction on the thread's hub,
+        // until another request on the same thread replaces it
+        Hub::current().configure_scope(|scope| scope.set_span(span));
         transaction
     }
+
+    fn finish_transaction(
+        settings: &Settings,
+        request: &Request,
+        response: &Response,
+        transaction: &Transaction,
+    ) {
+        // Routing is done by now, so the transaction can be named after the matched route
+        set_transaction_name(transaction, request);
+        transaction.set_status(map_status(response.status()));
+        transaction::apply_overrides(request, transaction);
+        let mut sentry_request =
+            request_to_sentry_request(request, &settings.scrubber, &settings.trusted_proxies);
+        if !settings.transaction_request_bodies {
+            sentry_request.data = None;
+        }
+        transaction.set_request(sentry_request);
+        // Finishing applies the current scope to the transaction, so use the request's one
+        let origin = TransactionOrigin {
+            request: request
+                .local_cache(|| CachedRequestMetadata(None))
+                .0
+                .clone(),
+            dynamic_sampling_context: propagation::request_to_dynamic_sampling_context(
+                request,
+                settings.trace_propagation,
+            ),
+        };
+        before_send::with_transaction_origin(origin, || match get_request_hub(request) {
+            Some(hub) => Hub::run(hub.clone(), || transaction.clone().finish()),
+            None => transaction.clone().finish(),
+        });
+    }
 }
 
 #[rocket::async_trait]
@@ -126,55 +444,322 @@ impl Fairing for RocketSentry {
     fn info(&self) -> Info {
         Info {
             name: "rocket-sentry",
-            kind: Kind::Ignite | Kind::Singleton | Kind::Request | Kind::Response,
+            kind: Kind::Ignite | Kind::Singleton | Kind::Request | Kind::Response | Kind::Shutdown,
         }
     }
 
     async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
         let figment = rocket.figment();
-        let profile_name = figment.profile().to_string();
-
-        // Set Sentry's environment based on Rocket profile
-        let environment = match profile_name.as_str() {
-            "debug" => Cow::Borrowed("development"),
-            "release" => Cow::Borrowed("production"),
-            _ => Cow::Owned(profile_name),
-        };
-
-        let config: figment::error::Result<Config> = figment.extract();
-        match config {
-            Ok(config) => {
-                if config.sentry_dsn.is_empty() {
-                    info!("Sentry disabled.");
-                } else {
-                    let traces_sample_rate = config.sentry_traces_sample_rate.unwrap_or(0f32);
-                    self.init(&config.sentry_dsn, traces_sample_rate, environment);
-                }
+        let required = SentryConfig::required_from_figment(figment)
+            .or(self.config.required)
+            .unwrap_or(false);
+        let result = self.configure(figment, required);
+        let rocket = rocket.manage(self.handle.clone());
+        match result {
+            Ok(()) => Ok(rocket),
+            Err(err) if required => {
+                error!("Sentry is required but not configured: {err}");
+                Err(rocket)
+            }
+            Err(err) => {
+                error!("Sentry not configured: {err}");
+                Ok(rocket)
             }
-            Err(err) => error!("Sentry not configured: {err}"),
         }
-        Ok(rocket)
     }
 
-    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
-        if self.transactions_enabled.load(Ordering::Relaxed) {
-            let name = request_to_transaction_name(request);
-            let build_transaction = move || Some(Self::start_transaction(&name));
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        let Some(main_hub) = self.handle.hub() else {
+            return;
+        };
+        self.handle.count_request();
+        // Each request gets its own hub, so that scope changes don't leak between requests
+        let hub = request
+            .local_cache(|| RequestHub(OnceLock::new()))
+            .0
+            .get_or_init(|| Arc::new(Hub::new_from_top(main_hub)))
+            .clone();
+        let Some(settings) = self.settings.get() else {
+            return;
+        };
+        let mut user = match &self.user_identifier {
+            Some(user_identifier) => user_identifier.identify(request).await,
+            None => None,
+        };
+        let client_ip = proxy::client_ip(request, &settings.trusted_proxies);
+        if let Some(client_ip) = settings.scrubber.client_ip(client_ip) {
+            let user = user.get_or_insert_with(User::default);
+            user.ip_address.get_or_insert(IpAddress::Exact(client_ip));
+        }
+        if user.is_some() {
+            hub.configure_scope(|scope| scope.set_user(user));
+        }
+        if settings.max_request_body_size > 0 {
+            let limit = settings.max_request_body_size;
+            let body = body::capture(request, data, limit, &settings.scrubber).await;
+            request.local_cache(|| RequestBody(body));
+        }
+        let metadata = (self.before_send.is_some() || self.before_send_transaction.is_some())
+            .then(|| Arc::new(RequestMetadata::new(request, client_ip)));
+        request.local_cache(|| CachedRequestMetadata(metadata.clone()));
+        // Events captured while the request is in flight, including panics, describe the request
+        let sentry_request =
+            request_to_sentry_request(request, &settings.scrubber, &settings.trusted_proxies);
+        hub.configure_scope(|scope| {
+            scope.set_transaction(Some(&request_to_transaction_name(request)));
+            scope.add_event_processor(move |mut event| {
+                if event.request.is_none() {
+                    event.request = Some(sentry_request.clone());
+                }
+                if let Some(metadata) = &metadata {
+                    before_send::set_event_request(event.event_id, metadata.clone());
+                }
+                Some(event)
+            });
+        });
+
+        if self.transactions_enabled.load(Ordering::Relaxed)
+            && !settings.ignore.ignores_request(request)
+        {
+            let mut transaction_context = propagation::request_to_transaction_context(
+                request,
+                &request_to_transaction_name(request),
+                TRANSACTION_OPERATION_NAME,
+                settings.trace_propagation,
+            );
+            let rate = match &self.request_sampler {
+                Some(request_sampler) => request_sampler(request).rate(),
+                None => None,
+            };
+            let sampled = transaction_context.sampled();
+            let rate = rate.or_else(|| settings.traces_sample_rules.rate(request, sampled));
+            if let Some(rate) = rate {
+                sampling::set_sample_rate(&mut transaction_context, rate);
+            }
+            let transaction = Self::start_transaction(&hub, transaction_context);
+            self.handle.count_transaction();
+            let mut sentry_request =
+                request_to_sentry_request(request, &settings.scrubber, &settings.trusted_proxies);
+            if !settings.transaction_request_bodies {
+                sentry_request.data = None;
+            }
+            let in_flight = InFlight {
+                transaction: transaction.clone(),
+                hub: hub.clone(),
+                request: sentry_request,
+                overrides: request
+                    .local_cache(Arc::<TransactionOverrides>::default)
+                    .clone(),
+                origin: TransactionOrigin {
+                    request: request
+                        .local_cache(|| CachedRequestMetadata(None))
+                        .0
+                        .clone(),
+                    dynamic_sampling_context: propagation::request_to_dynamic_sampling_context(
+                        request,
+                        settings.trace_propagation,
+                    ),
+                },
+            };
+            self.in_flight.insert(request, in_flight);
+            let build_transaction = move || Some(transaction.clone());
             let request_transaction = local_cache_once!(request, build_transaction);
             request.local_cache(request_transaction);
         }
     }
 
     async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(settings) = self.settings.get() else {
+            return;
+        };
+        if let Some(hub) = get_request_hub(request) {
+            set_scope_route(hub, request);
+        }
+
+        if settings.capture_error_responses && settings.is_error_response(response) {
+            // Panics in handlers bound to the request hub are reported there, then respond with
+            // a 500, so requests that already have an event don't get another one
+            let hub = get_request_hub(request).filter(|hub| hub.last_event_id().is_none());
+            if let Some(hub) = hub {
+                let event = response_to_event(
+                    request,
+                    response.status(),
+                    &settings.scrubber,
+                    &settings.trusted_proxies,
+                );
+                hub.capture_event(event);
+                self.handle.count_error_response();
+            }
+        }
+
         if self.transactions_enabled.load(Ordering::Relaxed) {
             // We take the transaction set in the on_request callback
             if let Some(ongoing_transaction) = get_current_transaction(request) {
-                ongoing_transaction.set_status(map_status(response.status()));
-                set_transaction_request(ongoing_transaction, request);
-                ongoing_transaction.clone().finish();
+                self.in_flight
+                    .remove(ongoing_transaction.get_trace_context().span_id);
+                if settings.trace_response_headers {
+                    propagation::set_response_trace_headers(
+                        ongoing_transaction,
+                        request,
+                        response,
+                        settings.trace_propagation,
+                    );
+                }
+                // Transactions of ignored routes are dropped without finishing, so they aren't sent
+                if !request
+                    .route()
+                    .is_some_and(|route| settings.ignore.ignores_route(route))
+                {
+                    Self::finish_transaction(settings, request, response, ongoing_transaction);
+                }
             }
         }
     }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        // Shutdown fairings run as soon as graceful shutdown starts, while Rocket gives requests
+        // until the grace and mercy periods are over before cancelling them
+        let shutdown = &rocket.config().shutdown;
+        let timeout = Duration::from_secs(u64::from(shutdown.grace) + u64::from(shutdown.mercy));
+        self.in_flight.abort(timeout).await;
+
+        let Some(client) = self.handle.hub().and_then(|hub| hub.client()) else {
+            return;
+        };
+        let timeout = client.options().shutdown_timeout;
+        info!("Flushing Sentry events.");
+        if !self.handle.flush(timeout).await {
+            warn!("Sentry events were not flushed within {timeout:?}.");
+        }
+    }
+}
+
+/// Sentry wants some options as static strings, so each distinct one is leaked once, however
+/// many times Rocket ignites.
+fn leak_str(value: &str) -> &'static str {
+    static LEAKED: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut leaked = LEAKED.get_or_init(Mutex::default).lock().unwrap();
+    if let Some(value) = leaked.get(value) {
+        return value;
+    }
+    let value: &'static str = Box::leak(value.into());
+    leaked.insert(value);
+    value
+}
+
+/// The environment mapped to the Rocket profile, see `sentry.environments`.
+fn profile_environment(config: &SentryConfig, profile: &Profile) -> Option<String> {
+    config
+        .environments
+        .as_ref()?
+        .get(&profile.to_string())
+        .cloned()
+}
+
+/// Sentry's environment, either configured or based on the Rocket profile.
+fn environment(config: &SentryConfig, profile: &Profile) -> Cow<'static, str> {
+    if let Some(environment) = &config.environment {
+        return Cow::Owned(environment.clone());
+    }
+    let profile_name = profile.to_string();
+    match profile_name.as_str() {
+        "debug" => Cow::Borrowed("development"),
+        "release" => Cow::Borrowed("production"),
+        _ => Cow::Owned(profile_name),
+    }
+}
+
+/// The Sentry hub of a request in flight.
+///
+/// Earlier fairings may look it up before the fairing's `on_request` caches it, so it is set in
+/// place rather than cached once.
+struct RequestHub(OnceLock<Arc<Hub>>);
+
+fn get_request_hub<'r>(request: &'r Request) -> Option<&'r Arc<Hub>> {
+    request.local_cache(|| RequestHub(OnceLock::new())).0.get()
+}
+
+/// Wraps route handlers so that they run with their request's own Sentry [`Hub`].
+///
+/// Within wrapped handlers, [`Hub::current()`] and functions like [`sentry::capture_message`]
+/// use the request's scope, which has the request transaction set as the current span.
+///
+/// Other handlers run with the hub of whichever thread they are polled on. The fairing sets the
+/// request transaction as the current span there too, like it did before requests got their own
+/// hubs, but concurrent requests polled on the same thread overwrite each other's span and scope.
+/// Rocket doesn't let fairings replace the handlers of mounted routes, so this takes wrapping
+/// the routes when mounting them.
+///
+/// ```no_run
+/// # #[macro_use]
+/// # extern crate rocket;
+/// use rocket_sentry::RocketSentry;
+///
+/// #[get("/")]
+/// fn index() -> &'static str {
+///     sentry::capture_message("Hello", sentry::Level::Info);
+///     "Hello"
+/// }
+///
+/// # fn main() {
+/// #[launch]
+/// fn rocket() -> _ {
+///     rocket::build()
+///         .attach(RocketSentry::fairing())
+///         .mount("/", rocket_sentry::bind_hub(routes![index]))
+/// }
+/// # }
+/// ```
+#[must_use]
+pub fn bind_hub(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(HubHandler(route.handler));
+            route
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+struct HubHandler(Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for HubHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        match get_request_hub(request) {
+            Some(hub) => {
+                // Routing is done by now, so events from the handler can refer to the route
+                if let Some(transaction) = get_current_transaction(request) {
+                    set_transaction_name(transaction, request);
+                }
+                set_scope_route(hub, request);
+                self.0.handle(request, data).bind_hub(hub.clone()).await
+            }
+            None => self.0.handle(request, data).await,
+        }
+    }
+}
+
+fn set_scope_route(hub: &Hub, request: &Request) {
+    hub.configure_scope(|scope| {
+        scope.set_transaction(Some(&routed_transaction_name(request)));
+        if let Some(route) = request.route() {
+            scope.set_context(ROUTE_CONTEXT, route_to_context(route));
+        }
+    });
+}
+
+fn route_to_context(route: &Route) -> protocol::Context {
+    let mut context = protocol::Map::new();
+    if let Some(name) = &route.name {
+        context.insert("name".into(), name.to_string().into());
+    }
+    context.insert("method".into(), route.method.as_str().into());
+    context.insert("uri".into(), route.uri.as_str().into());
+    context.insert("base".into(), route.uri.base().into());
+    context.insert("rank".into(), route.rank.into());
+    protocol::Context::Other(context)
 }
 
 fn get_current_transaction<'r>(request: &'r Request) -> Option<&'r Transaction> {
@@ -188,26 +773,104 @@ fn get_current_transaction<'r>(request: &'r Request) -> Option<&'r Transaction>
     ongoing_transaction.as_ref()
 }
 
-fn set_transaction_request(transaction: &Transaction, request: &Request) {
-    transaction.set_request(protocol::Request {
-        url: None,
+fn request_to_sentry_request(
+    request: &Request,
+    scrubber: &Scrubber,
+    trusted_proxies: &[TrustedProxy],
+) -> protocol::Request {
+    protocol::Request {
+        url: proxy::request_to_url(request, trusted_proxies),
         method: Some(request.method().to_string()),
-        data: None,
-        query_string: request_to_query_string(request),
-        cookies: None,
-        headers: request_to_header_map(request),
-        env: BTreeMap::new(),
-    });
+        data: request.local_cache(|| RequestBody(None)).0.clone(),
+        query_string: request_to_query_string(request, scrubber),
+        cookies: scrubber.cookies(request.cookies()),
+        headers: request_to_header_map(request, scrubber),
+        env: request_to_env(request, scrubber, trusted_proxies),
+    }
 }
 
+/// Describes the connection as the client sees it, though Rocket doesn't expose the HTTP version
+/// of requests.
+fn request_to_env(
+    request: &Request,
+    scrubber: &Scrubber,
+    trusted_proxies: &[TrustedProxy],
+) -> BTreeMap<String, String> {
+    let mut env = BTreeMap::new();
+    let client_ip = proxy::client_ip(request, trusted_proxies);
+    if let Some(client_ip) = scrubber.client_ip(client_ip) {
+        env.insert(REMOTE_ADDR_ENV.to_string(), client_ip.to_string());
+    }
+    let scheme = proxy::request_to_scheme(request, trusted_proxies);
+    let https = if scheme.eq_ignore_ascii_case("https") {
+        "on"
+    } else {
+        "off"
+    };
+    env.insert(HTTPS_ENV.to_string(), https.to_string());
+    env
+}
+
+/// Builds an error event for a response with an error status, see `sentry_capture_error_responses`
+fn response_to_event(
+    request: &Request,
+    status: Status,
+    scrubber: &Scrubber,
+    trusted_proxies: &[TrustedProxy],
+) -> protocol::Event<'static> {
+    let transaction = routed_transaction_name(request);
+    protocol::Event {
+        level: Level::Error,
+        message: Some(format!("{transaction} responded with {status}")),
+        transaction: Some(transaction),
+        request: Some(request_to_sentry_request(
+            request,
+            scrubber,
+            trusted_proxies,
+        )),
+        tags: BTreeMap::from([(HTTP_STATUS_CODE_TAG.to_string(), status.code.to_string())]),
+        ..Default::default()
+    }
+}
+
+/// Names the transaction once routing is done, see [`routed_transaction_name`].
+fn set_transaction_name(transaction: &Transaction, request: &Request) {
+    transaction.set_name(&routed_transaction_name(request));
+    // A workaround for the missing `transaction_info`, see `TRANSACTION_SOURCE_KEY`
+    transaction.set_data(
+        TRANSACTION_SOURCE_KEY,
+        request_to_transaction_source(request).into(),
+    );
+}
+
+/// Names the transaction after the matched route template (e.g. `GET /users/<id>`), falling
+/// back to the raw request path while routing has not happened yet.
 fn request_to_transaction_name(request: &Request) -> String {
     let method = request.method();
-    let path = request.uri().path();
-    format!("{method} {path}")
+    match request.route() {
+        Some(route) => format!("{method} {}", route.uri.path()),
+        None => format!("{method} {}", request.uri().path()),
+    }
 }
 
-fn request_to_query_string(request: &Request) -> Option<String> {
-    Some(request.uri().query()?.to_string())
+/// Like [`request_to_transaction_name`], once routing is done: requests that didn't match a
+/// route are all named after [`UNMATCHED_PATH`], such as `GET <unmatched>`.
+fn routed_transaction_name(request: &Request) -> String {
+    match request.route() {
+        Some(_) => request_to_transaction_name(request),
+        None => format!("{} {UNMATCHED_PATH}", request.method()),
+    }
+}
+
+fn request_to_transaction_source(request: &Request) -> &'static str {
+    match request.route() {
+        Some(_) => "route",
+        None => "url",
+    }
+}
+
+fn request_to_query_string(request: &Request, scrubber: &Scrubber) -> Option<String> {
+    Some(scrubber.query_string(request.uri().query()?.as_str()))
 }
 
 fn map_status(status: Status) -> SpanStatus {
@@ -230,16 +893,33 @@ fn map_status(status: Status) -> SpanStatus {
     }
 }
 
-fn request_to_header_map(request: &Request) -> BTreeMap<String, String> {
+fn request_to_header_map(request: &Request, scrubber: &Scrubber) -> BTreeMap<String, String> {
     request
         .headers()
         .iter()
-        .map(|header| (header.name().to_string(), header.value().to_string()))
+        .map(|header| {
+            let name = header.name().as_str();
+            // Don't let the Cookie header bypass cookie filtering
+            let value = if header.name() == COOKIE_HEADER {
+                scrubber
+                    .cookies(request.cookies())
+                    .unwrap_or_else(|| FILTERED.to_string())
+            } else {
+                scrubber.header_value(name, header.value()).to_string()
+            };
+            (name.to_string(), value)
+        })
         .collect()
 }
 
 pub struct RocketSentryBuilder {
     traces_sampler: Option<Arc<TracesSampler>>,
+    request_sampler: Option<Arc<RequestSampler>>,
+    user_identifier: Option<Arc<dyn UserIdentifier>>,
+    configure_client_options: Option<Box<ConfigureClientOptions>>,
+    before_send: Option<Arc<BeforeSend>>,
+    before_send_transaction: Option<Arc<BeforeSendTransaction>>,
+    config: SentryConfig,
 }
 
 impl RocketSentryBuilder {
@@ -247,6 +927,12 @@ impl RocketSentryBuilder {
     fn new() -> RocketSentryBuilder {
         RocketSentryBuilder {
             traces_sampler: None,
+            request_sampler: None,
+            user_identifier: None,
+            configure_client_options: None,
+            before_send: None,
+            before_send_transaction: None,
+            config: SentryConfig::default(),
         }
     }
 
@@ -256,32 +942,362 @@ impl RocketSentryBuilder {
         self
     }
 
+    /// Decides whether to send the transaction of a request, with access to its headers, query
+    /// and remote address, unlike the [`traces_sampler`](Self::traces_sampler):
+    ///
+    /// ```
+    /// use rocket_sentry::{RocketSentry, SamplingDecision};
+    ///
+    /// RocketSentry::builder().request_sampler(|request| {
+    ///     if request.headers().contains("X-Debug") {
+    ///         SamplingDecision::Keep
+    ///     } else if request.uri().path().starts_with("/admin") {
+    ///         SamplingDecision::Rate(0.5)
+    ///     } else {
+    ///         SamplingDecision::Defer
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// It runs before the transaction starts, which is before routing, so `request.route()` is
+    /// still `None`. Its decision takes precedence over the sample rules and the sampling
+    /// decision of an incoming trace.
+    #[must_use]
+    pub fn request_sampler<F>(mut self, request_sampler: F) -> RocketSentryBuilder
+    where
+        F: Fn(&Request<'_>) -> SamplingDecision + Send + Sync + 'static,
+    {
+        self.request_sampler = Some(Arc::new(request_sampler));
+        self
+    }
+
+    /// The environment the events belong to. Defaults to `development` for the `debug` Rocket
+    /// profile, `production` for `release`, and the profile name otherwise.
+    ///
+    /// The `sentry_environment` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn environment(mut self, environment: impl Into<String>) -> RocketSentryBuilder {
+        self.config.environment = Some(environment.into());
+        self
+    }
+
+    /// Maps a Rocket profile to an environment, instead of the default mapping described in
+    /// [`environment`](Self::environment):
+    ///
+    /// ```
+    /// use rocket_sentry::RocketSentry;
+    ///
+    /// RocketSentry::builder()
+    ///     .profile_environment("release", "staging")
+    ///     .profile_environment("prod", "production");
+    /// ```
+    ///
+    /// Mappings of the same profile in the `sentry.environments` table in `Rocket.toml` take
+    /// precedence over this, other profiles keep their mapping from here.
+    #[must_use]
+    pub fn profile_environment(
+        mut self,
+        profile: impl Into<String>,
+        environment: impl Into<String>,
+    ) -> RocketSentryBuilder {
+        let environments = self.config.environments.get_or_insert_with(BTreeMap::new);
+        environments.insert(profile.into(), environment.into());
+        self
+    }
+
+    /// Abort the launch if Sentry can't be set up, such as when the DSN is missing or invalid,
+    /// instead of running without it. Defaults to `false`.
+    ///
+    /// The `sentry_required` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn required(mut self, required: bool) -> RocketSentryBuilder {
+        self.config.required = Some(required);
+        self
+    }
+
+    /// Which distributed tracing headers to accept, defaults to [`TracePropagation::Sentry`].
+    ///
+    /// The `sentry_trace_propagation` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn trace_propagation(mut self, trace_propagation: TracePropagation) -> RocketSentryBuilder {
+        self.config.trace_propagation = Some(trace_propagation);
+        self
+    }
+
+    /// Also emit the tracing headers of the request transaction on responses.
+    ///
+    /// The `sentry_trace_response_headers` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn trace_response_headers(mut self, enabled: bool) -> RocketSentryBuilder {
+        self.config.trace_response_headers = Some(enabled);
+        self
+    }
+
+    /// Capture an error event for responses with an error status, in addition to the transaction.
+    ///
+    /// Requests that already captured an event on their hub, such as a panic in a route wrapped
+    /// with [`bind_hub`], don't get another one.
+    ///
+    /// The `sentry_capture_error_responses` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn capture_error_responses(mut self, enabled: bool) -> RocketSentryBuilder {
+        self.config.capture_error_responses = Some(enabled);
+        self
+    }
+
+    /// Which response statuses [`capture_error_responses`](Self::capture_error_responses)
+    /// applies to, defaults to 500-599.
+    ///
+    /// The `sentry_error_response_status` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn error_response_status<I>(mut self, status: I) -> RocketSentryBuilder
+    where
+        I: IntoIterator,
+        I::Item: Into<StatusRange>,
+    {
+        self.config.error_response_status = Some(status.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Send personally identifiable information, such as credentials in headers and query
+    /// strings and the client's IP address, which is otherwise scrubbed. Also sets Sentry's
+    /// `send_default_pii` option.
+    ///
+    /// The `sentry_send_default_pii` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn send_default_pii(mut self, enabled: bool) -> RocketSentryBuilder {
+        self.config.send_default_pii = Some(enabled);
+        self
+    }
+
+    /// Additional rules for scrubbing headers and query parameters, see [`ScrubConfig`].
+    ///
+    /// The `sentry_scrub` table in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn scrub(mut self, scrub_config: ScrubConfig) -> RocketSentryBuilder {
+        self.config.scrub = Some(scrub_config);
+        self
+    }
+
+    /// Requests that don't get a transaction, such as health checks, see [`IgnoreConfig`].
+    ///
+    /// The `sentry.ignore` table in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn ignore(mut self, ignore_config: IgnoreConfig) -> RocketSentryBuilder {
+        self.config.ignore = Some(ignore_config);
+        self
+    }
+
+    /// Sample rates of the transactions of matching requests, instead of the
+    /// `sentry_traces_sample_rate`, see [`TracesSampleRule`].
+    ///
+    /// The `sentry.traces_sample_rules` in `Rocket.toml` take precedence over these.
+    #[must_use]
+    pub fn traces_sample_rules(mut self, rules: Vec<TracesSampleRule>) -> RocketSentryBuilder {
+        self.config.traces_sample_rules = Some(rules);
+        self
+    }
+
+    /// Reverse proxies whose `Forwarded` and `X-Forwarded-*` headers are believed when reporting
+    /// the request URL and client IP address, see [`TrustedProxy`]. No proxies are trusted by
+    /// default.
+    ///
+    /// The `sentry_trusted_proxies` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn trusted_proxies<I>(mut self, trusted_proxies: I) -> RocketSentryBuilder
+    where
+        I: IntoIterator,
+        I::Item: Into<TrustedProxy>,
+    {
+        self.config.trusted_proxies = Some(trusted_proxies.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Capture request bodies of up to this many bytes on events; larger bodies are only
+    /// summarised. Defaults to 0, which disables capturing request bodies.
+    ///
+    /// Bodies are peeked at without consuming them, so handlers still receive the whole body, but
+    /// Rocket only allows peeking at 512 bytes: larger sizes fail on ignite, see
+    /// [`required`](Self::required). Fields of JSON and form bodies are scrubbed like query
+    /// parameters, see [`ScrubConfig`], while binary bodies are only summarised.
+    ///
+    /// The `sentry_max_request_body_size` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn max_request_body_size(mut self, max_request_body_size: usize) -> RocketSentryBuilder {
+        self.config.max_request_body_size = Some(max_request_body_size);
+        self
+    }
+
+    /// Also attach captured request bodies to transactions, not only to events.
+    ///
+    /// The `sentry_transaction_request_bodies` setting in `Rocket.toml` takes precedence over this.
+    #[must_use]
+    pub fn transaction_request_bodies(mut self, enabled: bool) -> RocketSentryBuilder {
+        self.config.transaction_request_bodies = Some(enabled);
+        self
+    }
+
+    /// Identifies the user making each request, see [`UserIdentifier`].
+    #[must_use]
+    pub fn user_identifier(mut self, user_identifier: impl UserIdentifier) -> RocketSentryBuilder {
+        self.user_identifier = Some(Arc::new(user_identifier));
+        self
+    }
+
+    /// Adjusts the client options assembled from the Rocket configuration, right before Sentry
+    /// is initialized. This allows setting options that can't be configured, such as
+    /// integrations, `before_breadcrumb` or a custom transport:
+    ///
+    /// ```
+    /// use rocket_sentry::RocketSentry;
+    ///
+    /// RocketSentry::builder().client_options(|options| {
+    ///     options.before_breadcrumb = Some(std::sync::Arc::new(|breadcrumb| {
+    ///         (breadcrumb.category.as_deref() != Some("sql")).then_some(breadcrumb)
+    ///     }));
+    ///     options.default_integrations = false;
+    /// });
+    /// ```
+    #[must_use]
+    pub fn client_options<F>(mut self, configure: F) -> RocketSentryBuilder
+    where
+        F: Fn(&mut ClientOptions) + Send + Sync + 'static,
+    {
+        self.configure_client_options = Some(Box::new(configure));
+        self
+    }
+
+    /// Filters or modifies events before they are sent, given the request they were captured
+    /// during, if any. Return `None` to drop the event:
+    ///
+    /// ```
+    /// use rocket_sentry::RocketSentry;
+    ///
+    /// RocketSentry::builder().before_send(|mut event, request| {
+    ///     if let Some(tenant) = request.and_then(|request| request.headers.get_one("X-Tenant")) {
+    ///         event.tags.insert("tenant".to_string(), tenant.to_string());
+    ///     }
+    ///     Some(event)
+    /// });
+    /// ```
+    ///
+    /// This runs before the fairing's own `before_send`, which logs the events being sent.
+    /// Setting `before_send` through [`client_options`](Self::client_options) replaces both.
+    #[must_use]
+    pub fn before_send<F>(mut self, before_send: F) -> RocketSentryBuilder
+    where
+        F: Fn(
+                protocol::Event<'static>,
+                Option<&RequestMetadata>,
+            ) -> Option<protocol::Event<'static>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.before_send = Some(Arc::new(before_send));
+        self
+    }
+
+    /// Filters or modifies transactions before they are sent, given the request they were
+    /// started for, if any. Return `None` to drop the transaction:
+    ///
+    /// ```
+    /// use rocket::http::Method;
+    /// use rocket_sentry::RocketSentry;
+    ///
+    /// RocketSentry::builder().before_send_transaction(|transaction, request| {
+    ///     match request {
+    ///         Some(request) if request.method == Method::Options => None,
+    ///         _ => Some(transaction),
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// The Sentry SDK has no such option, so this wraps the transport, including one set through
+    /// [`client_options`](Self::client_options).
+    #[must_use]
+    pub fn before_send_transaction<F>(mut self, before_send_transaction: F) -> RocketSentryBuilder
+    where
+        F: Fn(
+                protocol::Transaction<'static>,
+                Option<&RequestMetadata>,
+            ) -> Option<protocol::Transaction<'static>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.before_send_transaction = Some(Arc::new(before_send_transaction));
+        self
+    }
+
+    /// Invalid settings, such as [`scrub`](Self::scrub) rules that aren't valid regular
+    /// expressions, are reported on ignite, see [`required`](Self::required).
     #[must_use]
     pub fn build(self) -> RocketSentry {
         RocketSentry {
             guard: Mutex::new(None),
+            handle: SentryHandle::default(),
             transactions_enabled: AtomicBool::new(false),
+            in_flight: InFlightRequests::default(),
             traces_sampler: self.traces_sampler,
+            request_sampler: self.request_sampler,
+            user_identifier: self.user_identifier,
+            configure_client_options: self.configure_client_options,
+            before_send: self.before_send,
+            before_send_transaction: self.before_send_transaction,
+            config: self.config,
+            settings: OnceLock::new(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use figment::Figment;
+    use rocket::http::uri::Host;
     use rocket::http::ContentType;
+    use rocket::http::Cookie;
     use rocket::http::Header;
+    use rocket::http::Status;
     use rocket::local::asynchronous::Client;
+    use rocket::request::{FromRequest, Outcome};
+    use rocket::{routes, uri, Request};
     use sentry::TransactionContext;
     use std::borrow::Cow;
+    use std::net::SocketAddr;
     use std::sync::atomic::Ordering;
     use std::sync::Arc;
 
     use crate::{
-        request_to_header_map, request_to_query_string, request_to_transaction_name, RocketSentry,
+        leak_str, request_to_header_map, request_to_query_string, request_to_sentry_request,
+        request_to_transaction_name, request_to_transaction_source, response_to_event,
+        routed_transaction_name, RocketSentry, SamplingDecision, ScrubConfig, Scrubber,
+        SentryConfig, StatusRange,
     };
 
     const DEFAULT_ENV: Cow<'static, str> = Cow::Borrowed("TEST");
 
+    /// Exposes the transaction name as computed once routing is done
+    struct TransactionName(String, &'static str);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for TransactionName {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            Outcome::Success(TransactionName(
+                request_to_transaction_name(request),
+                request_to_transaction_source(request),
+            ))
+        }
+    }
+
+    #[rocket::post("/users/<id>")]
+    fn user(id: u32, transaction_name: TransactionName) -> String {
+        let TransactionName(name, source) = transaction_name;
+        format!("{name} ({source}) for {id}")
+    }
+
     #[rocket::async_test]
     async fn request_to_sentry_transaction_name_get_no_path() {
         let rocket = rocket::build();
@@ -306,14 +1322,39 @@ mod tests {
 
     #[rocket::async_test]
     async fn request_to_sentry_transaction_name_post_path_with_variables() {
+        let rocket = rocket::build().mount("/", routes![user]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client.post("/users/6").dispatch().await;
+
+        let body = response.into_string().await.unwrap();
+
+        assert_eq!(body, "POST /users/<id> (route) for 6");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_sentry_transaction_name_mounted_route() {
+        let rocket = rocket::build().mount("/api", routes![user]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client.post("/api/users/7").dispatch().await;
+
+        let body = response.into_string().await.unwrap();
+
+        assert_eq!(body, "POST /api/users/<id> (route) for 7");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_sentry_transaction_source_unmatched() {
         let rocket = rocket::build();
         let client = Client::tracked(rocket).await.unwrap();
         let request = client.post("/users/6");
 
         let transaction_name = request_to_transaction_name(request.inner());
+        let transaction_source = request_to_transaction_source(request.inner());
 
-        // Ideally, we should just returns /users/<id> as configured in the routes
         assert_eq!(transaction_name, "POST /users/6");
+        assert_eq!(transaction_source, "url");
+        // Once routing is done, unmatched requests share a name
+        assert_eq!(routed_transaction_name(request.inner()), "POST <unmatched>");
     }
 
     #[rocket::async_test]
@@ -322,7 +1363,7 @@ mod tests {
         let client = Client::tracked(rocket).await.unwrap();
         let request = client.post("/");
 
-        let query_string = request_to_query_string(request.inner());
+        let query_string = request_to_query_string(request.inner(), &Scrubber::default());
 
         assert_eq!(query_string, None);
     }
@@ -333,7 +1374,7 @@ mod tests {
         let client = Client::tracked(rocket).await.unwrap();
         let request = client.post("/?param1=value1");
 
-        let query_string = request_to_query_string(request.inner());
+        let query_string = request_to_query_string(request.inner(), &Scrubber::default());
 
         assert_eq!(query_string, Some("param1=value1".to_string()));
     }
@@ -344,7 +1385,7 @@ mod tests {
         let client = Client::tracked(rocket).await.unwrap();
         let request = client.post("/?param1=value1&param2=value2");
 
-        let query_string = request_to_query_string(request.inner());
+        let query_string = request_to_query_string(request.inner(), &Scrubber::default());
 
         assert_eq!(
             query_string,
@@ -358,7 +1399,7 @@ mod tests {
         let client = Client::tracked(rocket).await.unwrap();
         let request = client.get("/");
 
-        let header_map = request_to_header_map(request.inner());
+        let header_map = request_to_header_map(request.inner(), &Scrubber::default());
 
         assert!(header_map.is_empty());
     }
@@ -372,7 +1413,7 @@ mod tests {
             .header(ContentType::JSON)
             .header(Header::new("custom-key", "custom-value"));
 
-        let header_map = request_to_header_map(request.inner());
+        let header_map = request_to_header_map(request.inner(), &Scrubber::default());
 
         assert_eq!(
             header_map.get("custom-key"),
@@ -384,12 +1425,185 @@ mod tests {
         );
     }
 
+    #[rocket::async_test]
+    async fn response_to_event_internal_server_error() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let mut request = client.get("/some/path?param1=value1");
+        request.inner_mut().set_host(Host::new(uri!("example.com")));
+
+        let event = response_to_event(
+            request.inner(),
+            Status::InternalServerError,
+            &Scrubber::default(),
+            &[],
+        );
+
+        assert_eq!(event.level, sentry::Level::Error);
+        assert_eq!(
+            event.message.as_deref(),
+            Some("GET <unmatched> responded with 500 Internal Server Error")
+        );
+        assert_eq!(event.transaction.as_deref(), Some("GET <unmatched>"));
+        assert_eq!(event.tags.get("http.status_code"), Some(&"500".to_string()));
+        let sentry_request = event.request.unwrap();
+        assert_eq!(sentry_request.method.as_deref(), Some("GET"));
+        assert_eq!(
+            sentry_request.url.map(String::from).as_deref(),
+            Some("http://example.com/some/path")
+        );
+        assert_eq!(
+            sentry_request.query_string.as_deref(),
+            Some("param1=value1")
+        );
+    }
+
+    #[test]
+    fn status_range_from_config() {
+        let figment = Figment::from(("status", (503, "500-502", "418")));
+
+        let ranges: Vec<StatusRange> = figment.extract_inner("status").unwrap();
+
+        assert_eq!(
+            ranges,
+            vec![
+                StatusRange::from(503),
+                StatusRange::from(500..=502),
+                StatusRange::from(418),
+            ]
+        );
+        assert!(ranges[1].contains(Status::BadGateway));
+        assert!(!ranges[1].contains(Status::ServiceUnavailable));
+    }
+
+    #[test]
+    fn leak_str_once() {
+        let module = String::from("rocket_sentry_leak_str_once");
+
+        assert!(std::ptr::eq(leak_str(&module), leak_str(&module.clone())));
+    }
+
+    #[test]
+    fn status_range_from_config_invalid() {
+        let figment = Figment::from(("status", ("5xx",)));
+
+        let ranges: figment::Result<Vec<StatusRange>> = figment.extract_inner("status");
+
+        assert!(ranges.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn request_to_header_map_scrubbed() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/?token=secret&page=1")
+            .header(Header::new("Authorization", "Bearer secret"));
+
+        let header_map = request_to_header_map(request.inner(), &Scrubber::default());
+        let query_string = request_to_query_string(request.inner(), &Scrubber::default());
+
+        assert_eq!(
+            header_map.get("Authorization"),
+            Some(&"[Filtered]".to_string())
+        );
+        assert_eq!(query_string, Some("token=[Filtered]&page=1".to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn request_to_sentry_request_cookies() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/")
+            .cookie(Cookie::new("session", "secret"))
+            .cookie(Cookie::new("locale", "en"))
+            // Local requests only fill the cookie jar
+            .header(Header::new("Cookie", "session=secret; locale=en"));
+        let scrubber = Scrubber::new(
+            &ScrubConfig {
+                allow_cookies: vec!["locale".to_string()],
+                ..Default::default()
+            },
+            true,
+        )
+        .unwrap();
+
+        let sentry_request = request_to_sentry_request(request.inner(), &scrubber, &[]);
+
+        // Without the `secrets` feature, private cookies can't be told apart
+        let expected = if cfg!(feature = "secrets") {
+            "locale=en; session=[Filtered]".to_string()
+        } else {
+            "locale=[Filtered]; session=[Filtered]".to_string()
+        };
+        assert_eq!(sentry_request.cookies, Some(expected.clone()));
+        assert_eq!(sentry_request.headers.get("Cookie"), Some(&expected));
+    }
+
+    #[rocket::async_test]
+    async fn request_to_sentry_request_env() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/")
+            .remote("192.0.2.1:4000".parse::<SocketAddr>().unwrap());
+        let scrubber = Scrubber::new(&ScrubConfig::default(), true).unwrap();
+
+        let sentry_request = request_to_sentry_request(request.inner(), &scrubber, &[]);
+        let scrubbed_request =
+            request_to_sentry_request(request.inner(), &Scrubber::default(), &[]);
+
+        assert_eq!(
+            sentry_request.env.get("REMOTE_ADDR").map(String::as_str),
+            Some("192.0.2.1")
+        );
+        assert_eq!(
+            sentry_request.env.get("HTTPS").map(String::as_str),
+            Some("off")
+        );
+        assert_eq!(scrubbed_request.env.get("REMOTE_ADDR"), None);
+    }
+
+    #[cfg(feature = "secrets")]
+    #[rocket::async_test]
+    async fn request_to_sentry_request_private_cookies() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client.get("/").private_cookie(Cookie::new("locale", "en"));
+        let scrubber = Scrubber::new(
+            &ScrubConfig {
+                allow_cookies: vec!["locale".to_string()],
+                ..Default::default()
+            },
+            true,
+        )
+        .unwrap();
+
+        let sentry_request = request_to_sentry_request(request.inner(), &scrubber, &[]);
+
+        assert_eq!(
+            sentry_request.cookies,
+            Some("locale=[Filtered]".to_string())
+        );
+    }
+
+    fn init(rocket_sentry: &RocketSentry, traces_sample_rate: f32) {
+        let config = SentryConfig {
+            dsn: Some("https://user@some.dsn/123".to_string()),
+            traces_sample_rate: Some(traces_sample_rate),
+            ..Default::default()
+        };
+        let options = rocket_sentry.client_options(&config, DEFAULT_ENV);
+        rocket_sentry.init(options.unwrap()).unwrap();
+    }
+
     /// Transaction are only enabled on positive `traces_sample_rate` or a set `traces_sampler`
     #[rocket::async_test]
     async fn transactions_not_enabled() {
         let rocket_sentry = RocketSentry::builder().build();
 
-        rocket_sentry.init("https://user@some.dsn/123", 0., DEFAULT_ENV);
+        init(&rocket_sentry, 0.);
 
         assert!(!rocket_sentry.transactions_enabled.load(Ordering::Relaxed));
     }
@@ -398,7 +1612,7 @@ mod tests {
     async fn transactions_enabled_by_traces_sample_rate() {
         let rocket_sentry = RocketSentry::builder().build();
 
-        rocket_sentry.init("https://user@some.dsn/123", 0.01, DEFAULT_ENV);
+        init(&rocket_sentry, 0.01);
 
         assert!(rocket_sentry.transactions_enabled.load(Ordering::Relaxed));
     }
@@ -411,7 +1625,18 @@ mod tests {
             }))
             .build();
 
-        rocket_sentry.init("https://user@some.dsn/123", 0., DEFAULT_ENV);
+        init(&rocket_sentry, 0.);
+
+        assert!(rocket_sentry.transactions_enabled.load(Ordering::Relaxed));
+    }
+
+    #[rocket::async_test]
+    async fn transactions_enabled_by_request_sampler() {
+        let rocket_sentry = RocketSentry::builder()
+            .request_sampler(|_| SamplingDecision::Defer)
+            .build();
+
+        init(&rocket_sentry, 0.);
 
         assert!(rocket_sentry.transactions_enabled.load(Ordering::Relaxed));
     }
@@ -0,0 +1,432 @@
+//! Distributed tracing: continuing incoming traces and propagating them back on responses.
+//!
+//! Both Sentry's own (`sentry-trace` and `baggage`) and W3C Trace Context (`traceparent` and
+//! `tracestate`) headers are supported, see [`TracePropagation`].
+
+use std::collections::BTreeMap;
+
+use rocket::http::{Header, RawStr};
+use rocket::serde::Deserialize;
+use rocket::{Request, Response};
+use sentry::protocol::DynamicSamplingContext;
+use sentry::{protocol, SentryTrace, Transaction, TransactionContext};
+
+const SENTRY_TRACE_HEADER: &str = "sentry-trace";
+const BAGGAGE_HEADER: &str = "baggage";
+/// Only `sentry-` prefixed baggage entries make up the dynamic sampling context
+const BAGGAGE_SENTRY_PREFIX: &str = "sentry-";
+const TRACEPARENT_HEADER: &str = "traceparent";
+const TRACESTATE_HEADER: &str = "tracestate";
+/// The only `traceparent` version defined so far, see <https://www.w3.org/TR/trace-context/#version>
+const TRACEPARENT_VERSION: &str = "00";
+
+/// Which distributed tracing headers are accepted on requests and emitted on responses.
+///
+/// Configured with the `sentry_trace_propagation` setting in `Rocket.toml`, or
+/// [`RocketSentryBuilder::trace_propagation`](crate::RocketSentryBuilder::trace_propagation).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum TracePropagation {
+    /// Sentry's `sentry-trace` and `baggage` headers (default).
+    #[default]
+    Sentry,
+    /// W3C Trace Context `traceparent` and `tracestate` headers, as used by OpenTelemetry.
+    W3c,
+    /// Both, with `sentry-trace` taking precedence when a request carries both.
+    Both,
+}
+
+impl TracePropagation {
+    fn sentry(self) -> bool {
+        matches!(self, TracePropagation::Sentry | TracePropagation::Both)
+    }
+
+    fn w3c(self) -> bool {
+        matches!(self, TracePropagation::W3c | TracePropagation::Both)
+    }
+}
+
+/// Continues the distributed trace from the incoming tracing headers, if any.
+///
+/// The upstream dynamic sampling context is made available to `traces_sampler` under the
+/// `baggage` key of the custom sampling context, and W3C `tracestate` under `tracestate`.
+pub(crate) fn request_to_transaction_context(
+    request: &Request,
+    name: &str,
+    op: &str,
+    propagation: TracePropagation,
+) -> TransactionContext {
+    let Some(sentry_trace) = request_to_sentry_trace(request, propagation) else {
+        return TransactionContext::new(name, op);
+    };
+    let mut transaction_context =
+        TransactionContext::continue_from_sentry_trace(name, op, &sentry_trace, None);
+
+    if propagation.w3c() {
+        if let Some(tracestate) = request.headers().get_one(TRACESTATE_HEADER) {
+            transaction_context.custom_insert(TRACESTATE_HEADER.to_string(), tracestate.into());
+        }
+    }
+
+    // Baggage belongs to Sentry's propagation, even when the trace comes from `traceparent`
+    let baggage = if propagation.sentry() {
+        request_to_sentry_baggage(request)
+    } else {
+        BTreeMap::new()
+    };
+    if baggage.is_empty() {
+        return transaction_context;
+    }
+    if transaction_context.sampled().is_none() {
+        // The sentry-trace header may omit the sampling decision, fall back to the baggage one
+        let sampled = baggage.get("sampled").and_then(|s| s.parse().ok());
+        transaction_context.set_sampled(sampled);
+    }
+    let baggage = baggage
+        .into_iter()
+        .map(|(key, value)| (key, value.into()))
+        .collect();
+    transaction_context.custom_insert(BAGGAGE_HEADER.to_string(), protocol::Value::Object(baggage));
+    transaction_context
+}
+
+/// The dynamic sampling context of an incoming Sentry trace, from the `sentry-` entries of its
+/// `baggage`.
+///
+/// The head of the trace decides it, so it is sent unchanged with the transaction instead of the
+/// one the SDK fills in, see <https://develop.sentry.dev/sdk/telemetry/traces/dynamic-sampling-context/>.
+pub(crate) fn request_to_dynamic_sampling_context(
+    request: &Request,
+    propagation: TracePropagation,
+) -> Option<DynamicSamplingContext> {
+    if !propagation.sentry() || !request.headers().contains(SENTRY_TRACE_HEADER) {
+        return None;
+    }
+    let baggage = request_to_sentry_baggage(request);
+    if baggage.is_empty() {
+        return None;
+    }
+    let baggage = baggage
+        .into_iter()
+        .map(|(key, value)| (key, value.into()))
+        .collect();
+    // Malformed entries leave the SDK's own context in place
+    serde_json::from_value(protocol::Value::Object(baggage)).ok()
+}
+
+fn request_to_sentry_trace(
+    request: &Request,
+    propagation: TracePropagation,
+) -> Option<SentryTrace> {
+    let headers = request.headers();
+    let sentry_trace = headers
+        .get_one(SENTRY_TRACE_HEADER)
+        .filter(|_| propagation.sentry())
+        .map(|value| (SENTRY_TRACE_HEADER, value));
+
+    sentry::parse_headers(sentry_trace).or_else(|| {
+        let traceparent = headers
+            .get_one(TRACEPARENT_HEADER)
+            .filter(|_| propagation.w3c())?;
+        parse_traceparent(traceparent)
+    })
+}
+
+/// Parses the `sentry-` entries of all `baggage` headers, with the prefix stripped from keys.
+///
+/// See <https://www.w3.org/TR/baggage/#header-content>
+fn request_to_sentry_baggage(request: &Request) -> BTreeMap<String, String> {
+    request
+        .headers()
+        .get(BAGGAGE_HEADER)
+        .flat_map(|header| header.split(','))
+        .filter_map(|member| {
+            // Member properties, following a ';', are not used by Sentry
+            let entry = member.split(';').next()?;
+            let (key, value) = entry.split_once('=')?;
+            let key = key.trim().strip_prefix(BAGGAGE_SENTRY_PREFIX)?;
+            let value = RawStr::new(value.trim()).percent_decode().ok()?;
+            Some((key.to_string(), value.into_owned()))
+        })
+        .collect()
+}
+
+/// Parses a W3C `traceparent` header, see <https://www.w3.org/TR/trace-context/#traceparent-header>
+fn parse_traceparent(header: &str) -> Option<SentryTrace> {
+    let mut parts = header.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+
+    // The version is two lowercase hex digits, where ff is invalid
+    let is_lower_hex = |b: u8| b.is_ascii_digit() || (b'a'..=b'f').contains(&b);
+    if version.len() != 2 || !version.bytes().all(is_lower_hex) || version == "ff" {
+        return None;
+    }
+    // Future versions may append fields, but version 00 must have exactly four
+    if version == TRACEPARENT_VERSION && parts.next().is_some() {
+        return None;
+    }
+    // All-zero IDs are invalid
+    if [trace_id, parent_id]
+        .iter()
+        .any(|id| id.bytes().all(|b| b == b'0'))
+    {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+
+    Some(SentryTrace::new(
+        trace_id.parse().ok()?,
+        parent_id.parse().ok()?,
+        Some(flags & 1 == 1),
+    ))
+}
+
+/// Emits the tracing headers of the request transaction on the response, so the caller can
+/// link up with it.
+pub(crate) fn set_response_trace_headers(
+    transaction: &Transaction,
+    request: &Request,
+    response: &mut Response,
+    propagation: TracePropagation,
+) {
+    if propagation.sentry() {
+        for (name, value) in transaction.iter_headers() {
+            response.set_header(Header::new(name, value));
+        }
+    }
+    if propagation.w3c() {
+        let trace_context = transaction.get_trace_context();
+        let flags = u8::from(transaction.is_sampled());
+        let traceparent = format!(
+            "{TRACEPARENT_VERSION}-{}-{}-{flags:02x}",
+            trace_context.trace_id, trace_context.span_id
+        );
+        response.set_header(Header::new(TRACEPARENT_HEADER, traceparent));
+        // tracestate must be passed on unmodified if we don't participate in it
+        if let Some(tracestate) = request.headers().get_one(TRACESTATE_HEADER) {
+            response.set_header(Header::new(TRACESTATE_HEADER, tracestate.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+
+    use super::{
+        parse_traceparent, request_to_dynamic_sampling_context, request_to_sentry_baggage,
+        request_to_transaction_context, TracePropagation,
+    };
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+    const SENTRY_TRACE: &str = "09e04486820349518ac7b5d2adbf6ba5-9cf635fa5b870b3a-0";
+
+    #[rocket::async_test]
+    async fn request_to_transaction_context_new_trace() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client.get("/");
+
+        let transaction_context =
+            request_to_transaction_context(request.inner(), "GET /", "op", TracePropagation::Both);
+
+        assert_eq!(transaction_context.name(), "GET /");
+        assert_eq!(transaction_context.sampled(), None);
+        assert!(transaction_context.custom().is_none());
+    }
+
+    #[rocket::async_test]
+    async fn request_to_transaction_context_continues_trace() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/")
+            .header(Header::new("sentry-trace", SENTRY_TRACE))
+            .header(Header::new(
+                "baggage",
+                "sentry-trace_id=09e04486820349518ac7b5d2adbf6ba5,sentry-release=a%20b",
+            ));
+
+        let transaction_context = request_to_transaction_context(
+            request.inner(),
+            "GET /",
+            "op",
+            TracePropagation::Sentry,
+        );
+
+        assert_eq!(
+            transaction_context.trace_id().to_string(),
+            "09e04486820349518ac7b5d2adbf6ba5"
+        );
+        assert_eq!(transaction_context.sampled(), Some(false));
+        let baggage = &transaction_context.custom().unwrap()["baggage"];
+        assert_eq!(baggage["release"], "a b");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_dynamic_sampling_context_from_baggage() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/")
+            .header(Header::new("sentry-trace", SENTRY_TRACE))
+            .header(Header::new(
+                "baggage",
+                "sentry-trace_id=09e04486820349518ac7b5d2adbf6ba5,sentry-public_key=abc,\
+                 sentry-sample_rate=0.5,sentry-sampled=false,sentry-release=a%20b,other=1",
+            ));
+
+        let sentry = request_to_dynamic_sampling_context(request.inner(), TracePropagation::Sentry);
+        let w3c = request_to_dynamic_sampling_context(request.inner(), TracePropagation::W3c);
+
+        let sentry = serde_json::to_value(sentry.unwrap()).unwrap();
+        assert_eq!(sentry["trace_id"], "09e04486820349518ac7b5d2adbf6ba5");
+        assert_eq!(sentry["public_key"], "abc");
+        assert_eq!(sentry["sample_rate"], "0.5");
+        assert_eq!(sentry["sampled"], "false");
+        assert_eq!(sentry["release"], "a b");
+        assert!(w3c.is_none());
+    }
+
+    #[rocket::async_test]
+    async fn request_to_transaction_context_sampled_from_baggage() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/")
+            .header(Header::new(
+                "sentry-trace",
+                "09e04486820349518ac7b5d2adbf6ba5-9cf635fa5b870b3a",
+            ))
+            .header(Header::new("baggage", "sentry-sampled=true"));
+
+        let transaction_context = request_to_transaction_context(
+            request.inner(),
+            "GET /",
+            "op",
+            TracePropagation::Sentry,
+        );
+
+        assert_eq!(transaction_context.sampled(), Some(true));
+    }
+
+    #[rocket::async_test]
+    async fn request_to_transaction_context_w3c() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/")
+            .header(Header::new("traceparent", TRACEPARENT))
+            .header(Header::new("tracestate", "vendor=value"));
+
+        let sentry_only = request_to_transaction_context(
+            request.inner(),
+            "GET /",
+            "op",
+            TracePropagation::Sentry,
+        );
+        let w3c =
+            request_to_transaction_context(request.inner(), "GET /", "op", TracePropagation::W3c);
+
+        assert_eq!(sentry_only.sampled(), None);
+        assert_eq!(
+            w3c.trace_id().to_string(),
+            "0af7651916cd43dd8448eb211c80319c"
+        );
+        assert_eq!(w3c.sampled(), Some(true));
+        assert_eq!(w3c.custom().unwrap()["tracestate"], "vendor=value");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_transaction_context_w3c_ignores_baggage() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/")
+            .header(Header::new("baggage", "sentry-sampled=false"));
+
+        let w3c =
+            request_to_transaction_context(request.inner(), "GET /", "op", TracePropagation::W3c);
+
+        assert_eq!(w3c.sampled(), None);
+        assert!(w3c
+            .custom()
+            .map_or(true, |custom| !custom.contains_key("baggage")));
+    }
+
+    #[rocket::async_test]
+    async fn request_to_transaction_context_prefers_sentry_trace() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/")
+            .header(Header::new("traceparent", TRACEPARENT))
+            .header(Header::new("sentry-trace", SENTRY_TRACE));
+
+        let transaction_context =
+            request_to_transaction_context(request.inner(), "GET /", "op", TracePropagation::Both);
+
+        assert_eq!(
+            transaction_context.trace_id().to_string(),
+            "09e04486820349518ac7b5d2adbf6ba5"
+        );
+    }
+
+    #[test]
+    fn parse_traceparent_invalid() {
+        // Unknown flags are fine
+        assert!(
+            parse_traceparent("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-ff").is_some()
+        );
+        // Future versions may have more fields
+        assert!(
+            parse_traceparent("01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00-x")
+                .is_some()
+        );
+
+        assert!(parse_traceparent("").is_none());
+        assert!(
+            parse_traceparent("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00-x")
+                .is_none()
+        );
+        assert!(
+            parse_traceparent("ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00").is_none()
+        );
+        assert!(
+            parse_traceparent("00-00000000000000000000000000000000-b7ad6b7169203331-00").is_none()
+        );
+        assert!(
+            parse_traceparent("00-0af7651916cd43dd8448eb211c80319c-0000000000000000-00").is_none()
+        );
+        assert!(parse_traceparent("00-0af7651916cd43dd-b7ad6b7169203331-00").is_none());
+        assert!(
+            parse_traceparent("0A-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00").is_none()
+        );
+        assert!(
+            parse_traceparent("+1-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00").is_none()
+        );
+    }
+
+    #[rocket::async_test]
+    async fn request_to_sentry_baggage_skips_foreign_entries() {
+        let rocket = rocket::build();
+        let client = Client::tracked(rocket).await.unwrap();
+        let request = client
+            .get("/")
+            .header(Header::new(
+                "baggage",
+                "other=1, sentry-environment=prod;prop=x",
+            ))
+            .header(Header::new("baggage", "sentry-public_key=abc"));
+
+        let baggage = request_to_sentry_baggage(request.inner());
+
+        assert_eq!(baggage.len(), 2);
+        assert_eq!(baggage.get("environment"), Some(&"prod".to_string()));
+        assert_eq!(baggage.get("public_key"), Some(&"abc".to_string()));
+    }
+}
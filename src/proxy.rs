@@ -0,0 +1,464 @@
+//! Request details as seen by the client, which may differ from the connection Rocket sees
+//! when running behind reverse proxies.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use rocket::serde::de::{self, Visitor};
+use rocket::serde::{Deserialize, Deserializer};
+use rocket::Request;
+use sentry::protocol::Url;
+
+const FORWARDED_HEADER: &str = "Forwarded";
+const X_FORWARDED_PROTO_HEADER: &str = "X-Forwarded-Proto";
+const X_FORWARDED_HOST_HEADER: &str = "X-Forwarded-Host";
+const X_FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// A reverse proxy address, or network of addresses, whose forwarding headers are trusted.
+///
+/// Written as an IP address like `"10.0.0.1"` or a network like `"10.0.0.0/8"`, for example
+/// in `Rocket.toml`:
+///
+/// ```toml
+/// [release]
+/// sentry_trusted_proxies = ["127.0.0.1", "10.0.0.0/8"]
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl TrustedProxy {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len));
+                let mask = mask.unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len));
+                let mask = mask.unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for TrustedProxy {
+    fn from(ip: IpAddr) -> Self {
+        let prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+        TrustedProxy {
+            network: ip,
+            prefix_len,
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid IP address or network: {value:?}");
+        let Some((network, prefix_len)) = value.split_once('/') else {
+            let ip: IpAddr = value.trim().parse().map_err(|_| invalid())?;
+            return Ok(TrustedProxy::from(ip));
+        };
+        let network: IpAddr = network.trim().parse().map_err(|_| invalid())?;
+        let prefix_len: u8 = prefix_len.trim().parse().map_err(|_| invalid())?;
+        if prefix_len > TrustedProxy::from(network).prefix_len {
+            return Err(invalid());
+        }
+        Ok(TrustedProxy {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for TrustedProxy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TrustedProxyVisitor;
+
+        impl Visitor<'_> for TrustedProxyVisitor {
+            type Value = TrustedProxy;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an IP address or network like \"10.0.0.0/8\"")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<TrustedProxy, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(TrustedProxyVisitor)
+    }
+}
+
+/// Whether the request came through a trusted proxy, so its forwarding headers can be believed.
+pub(crate) fn is_trusted(request: &Request, trusted_proxies: &[TrustedProxy]) -> bool {
+    let Some(remote) = request.remote() else {
+        return false;
+    };
+    trusted_proxies
+        .iter()
+        .any(|proxy| proxy.contains(remote.ip()))
+}
+
+/// Rebuilds the URL the client requested, without the query string.
+///
+/// Requests without a `Host` header, as HTTP/1.0 allows, have no URL: the address Rocket is
+/// bound to says little about the one the client used.
+pub(crate) fn request_to_url(request: &Request, trusted_proxies: &[TrustedProxy]) -> Option<Url> {
+    let scheme = request_to_scheme(request, trusted_proxies);
+    let host = forwarded_value(request, trusted_proxies, "host", X_FORWARDED_HOST_HEADER)
+        .or_else(|| request.host().map(ToString::to_string))?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+
+    let path = request.uri().path();
+    Url::parse(&format!("{scheme}://{host}{path}")).ok()
+}
+
+/// The scheme the client requested, such as `https`, which a trusted proxy may have terminated.
+pub(crate) fn request_to_scheme(request: &Request, trusted_proxies: &[TrustedProxy]) -> String {
+    let scheme = forwarded_value(request, trusted_proxies, "proto", X_FORWARDED_PROTO_HEADER);
+    scheme.unwrap_or_else(|| {
+        let tls = request.rocket().config().tls_enabled();
+        if tls { "https" } else { "http" }.to_string()
+    })
+}
+
+/// The IP address of the client, as seen by the outermost trusted proxy, see [`outermost_hop`].
+///
+/// Without forwarding headers this is the remote address of the connection; unlike
+/// [`Request::client_ip()`], `X-Real-IP` isn't believed.
+pub(crate) fn client_ip(request: &Request, trusted_proxies: &[TrustedProxy]) -> Option<IpAddr> {
+    let remote_ip = || request.remote().map(|remote| remote.ip());
+    if !is_trusted(request, trusted_proxies) {
+        return remote_ip();
+    }
+    let hops = if request.headers().contains(FORWARDED_HEADER) {
+        forwarded_hops(request)
+    } else {
+        x_forwarded_hops(request)
+    };
+    match outermost_hop(&hops, trusted_proxies) {
+        // Addresses hidden by a proxy leave the client unknown beyond it
+        Some(index) => hops[index],
+        None => remote_ip(),
+    }
+}
+
+/// The index of the forwarding header element added by the outermost trusted proxy.
+///
+/// Proxies append the address they received a request from to the `Forwarded` or
+/// `X-Forwarded-For` header, so these are followed from the end for as long as the addresses
+/// belong to trusted proxies. Elements before that may have been written by the client.
+fn outermost_hop(hops: &[Option<IpAddr>], trusted_proxies: &[TrustedProxy]) -> Option<usize> {
+    let is_trusted = |hop: Option<IpAddr>| {
+        hop.is_some_and(|ip| trusted_proxies.iter().any(|proxy| proxy.contains(ip)))
+    };
+    let last = hops.len().checked_sub(1)?;
+    let outermost = (0..=last).rev().find(|&index| !is_trusted(hops[index]));
+    Some(outermost.unwrap_or(0))
+}
+
+/// The `for` addresses of the `Forwarded` header elements.
+fn forwarded_hops(request: &Request) -> Vec<Option<IpAddr>> {
+    header_values(request, FORWARDED_HEADER)
+        .map(|element| element_param(element, "for").and_then(|node| parse_node(&node)))
+        .collect()
+}
+
+fn x_forwarded_hops(request: &Request) -> Vec<Option<IpAddr>> {
+    header_values(request, X_FORWARDED_FOR_HEADER)
+        .map(parse_node)
+        .collect()
+}
+
+/// A parameter such as `proto` or `host`, as forwarded by the outermost trusted proxy in the
+/// `Forwarded` header, or else in the matching `X-Forwarded-*` header.
+fn forwarded_value(
+    request: &Request,
+    trusted_proxies: &[TrustedProxy],
+    param: &str,
+    x_forwarded_header: &str,
+) -> Option<String> {
+    if !is_trusted(request, trusted_proxies) {
+        return None;
+    }
+    let elements: Vec<&str> = header_values(request, FORWARDED_HEADER).collect();
+    let value = outermost_hop(&forwarded_hops(request), trusted_proxies)
+        .and_then(|index| element_param(elements[index], param));
+    if value.is_some() {
+        return value;
+    }
+
+    // Proxies appending to `X-Forwarded-For` append here too, so the values line up from the end;
+    // proxies that don't leave fewer values, of which the first is then the outermost one's
+    let values: Vec<&str> = header_values(request, x_forwarded_header).collect();
+    let hops = x_forwarded_hops(request);
+    let offset = outermost_hop(&hops, trusted_proxies).map_or(0, |index| hops.len() - 1 - index);
+    let index = values.len().checked_sub(1)?.saturating_sub(offset);
+    Some(values[index].to_string())
+}
+
+/// Parses an address like `192.0.2.1`, `192.0.2.1:4711`, `2001:db8::1` or `"[2001:db8::1]:4711"`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(node) = node.strip_prefix('[') {
+        return node.split(']').next()?.parse().ok();
+    }
+    node.parse()
+        .ok()
+        .or_else(|| node.rsplit_once(':')?.0.parse().ok())
+}
+
+/// All comma-separated values of a header, which may also be repeated.
+fn header_values<'r>(request: &'r Request, name: &str) -> impl Iterator<Item = &'r str> {
+    request
+        .headers()
+        .get(name)
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Returns a parameter of a `Forwarded` header element, see
+/// <https://www.rfc-editor.org/rfc/rfc7239#section-4>
+fn element_param(element: &str, name: &str) -> Option<String> {
+    element.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case(name) {
+            return None;
+        }
+        let value = value.trim().trim_matches('"');
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use rocket::http::uri::Host;
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+    use rocket::uri;
+
+    use super::{client_ip, request_to_scheme, request_to_url, TrustedProxy};
+
+    const PROXY: &str = "10.1.2.3:4000";
+
+    fn proxies() -> Vec<TrustedProxy> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    #[test]
+    fn trusted_proxy_contains() {
+        let network: TrustedProxy = "10.0.0.0/8".parse().unwrap();
+        let single: TrustedProxy = "::1".parse().unwrap();
+        let all: TrustedProxy = "0.0.0.0/0".parse().unwrap();
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        assert!(network.contains(ip("10.200.0.1")));
+        assert!(network.contains(ip("::ffff:10.0.0.1")));
+        assert!(!network.contains(ip("11.0.0.1")));
+        assert!(single.contains(ip("::1")));
+        assert!(!single.contains(ip("::2")));
+        assert!(all.contains(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn trusted_proxy_invalid() {
+        assert!("10.0.0.0/33".parse::<TrustedProxy>().is_err());
+        assert!("localhost".parse::<TrustedProxy>().is_err());
+        assert!("10.0.0.0/x".parse::<TrustedProxy>().is_err());
+    }
+
+    #[rocket::async_test]
+    async fn request_to_url_host_header() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let mut request = client.get("/some/path?token=x");
+        request
+            .inner_mut()
+            .set_host(Host::new(uri!("example.com:8000")));
+
+        let url = request_to_url(request.inner(), &proxies());
+
+        assert_eq!(url.unwrap().as_str(), "http://example.com:8000/some/path");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_url_without_host() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let request = client.get("/some/path");
+
+        assert_eq!(request_to_url(request.inner(), &proxies()), None);
+    }
+
+    #[rocket::async_test]
+    async fn request_to_url_untrusted_proxy() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let mut request = client
+            .get("/")
+            .remote("192.0.2.1:4000".parse::<SocketAddr>().unwrap())
+            .header(Header::new("X-Forwarded-Proto", "https"))
+            .header(Header::new("X-Forwarded-Host", "example.com"));
+        request.inner_mut().set_host(Host::new(uri!("internal")));
+
+        let url = request_to_url(request.inner(), &proxies());
+
+        assert_eq!(url.unwrap().as_str(), "http://internal/");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_url_x_forwarded() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let request = client
+            .get("/")
+            .remote(PROXY.parse::<SocketAddr>().unwrap())
+            .header(Header::new("X-Forwarded-For", "203.0.113.7, 10.0.0.2"))
+            .header(Header::new("X-Forwarded-Proto", "http, https, http"))
+            .header(Header::new("X-Forwarded-Host", "example.com"));
+
+        let url = request_to_url(request.inner(), &proxies());
+
+        assert_eq!(url.unwrap().as_str(), "https://example.com/");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_url_x_forwarded_spoofed() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        // The client sent the first values, the proxy appended its own
+        let request = client
+            .get("/")
+            .remote(PROXY.parse::<SocketAddr>().unwrap())
+            .header(Header::new("X-Forwarded-For", "203.0.113.7"))
+            .header(Header::new("X-Forwarded-Proto", "https, http"))
+            .header(Header::new(
+                "X-Forwarded-Host",
+                "evil.example.com, example.com",
+            ));
+
+        let url = request_to_url(request.inner(), &proxies());
+
+        assert_eq!(url.unwrap().as_str(), "http://example.com/");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_url_forwarded() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let request = client
+            .get("/a")
+            .remote(PROXY.parse::<SocketAddr>().unwrap())
+            .header(Header::new(
+                "Forwarded",
+                r#"for=192.0.2.60;proto=https;host="example.com", for=10.0.0.1"#,
+            ))
+            .header(Header::new("X-Forwarded-Host", "ignored.example.com"));
+
+        let url = request_to_url(request.inner(), &proxies());
+
+        assert_eq!(url.unwrap().as_str(), "https://example.com/a");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_url_forwarded_spoofed() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let request = client
+            .get("/a")
+            .remote(PROXY.parse::<SocketAddr>().unwrap())
+            .header(Header::new(
+                "Forwarded",
+                r#"for=10.0.0.1;proto=https;host=evil, for=192.0.2.60;proto=http;host="example.com""#,
+            ));
+
+        let url = request_to_url(request.inner(), &proxies());
+
+        assert_eq!(url.unwrap().as_str(), "http://example.com/a");
+    }
+
+    #[rocket::async_test]
+    async fn request_to_scheme_forwarded() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let proxied = client
+            .get("/")
+            .remote(PROXY.parse::<SocketAddr>().unwrap())
+            .header(Header::new("Forwarded", "proto=https"));
+        let direct = client
+            .get("/")
+            .remote("192.0.2.1:4000".parse::<SocketAddr>().unwrap())
+            .header(Header::new("X-Forwarded-Proto", "https"));
+
+        assert_eq!(request_to_scheme(proxied.inner(), &proxies()), "https");
+        assert_eq!(request_to_scheme(direct.inner(), &proxies()), "http");
+    }
+
+    #[rocket::async_test]
+    async fn client_ip_untrusted_proxy() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let request = client
+            .get("/")
+            .remote("192.0.2.1:4000".parse::<SocketAddr>().unwrap())
+            .header(Header::new("X-Forwarded-For", "203.0.113.7"))
+            .header(Header::new("X-Real-IP", "203.0.113.8"));
+
+        let ip = client_ip(request.inner(), &proxies());
+
+        assert_eq!(ip, Some("192.0.2.1".parse().unwrap()));
+    }
+
+    #[rocket::async_test]
+    async fn client_ip_x_forwarded_for() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let request = client
+            .get("/")
+            .remote(PROXY.parse::<SocketAddr>().unwrap())
+            .header(Header::new("X-Forwarded-For", "198.51.100.1, 203.0.113.7"))
+            .header(Header::new("X-Forwarded-For", "10.0.0.2"));
+
+        let ip = client_ip(request.inner(), &proxies());
+
+        assert_eq!(ip, Some("203.0.113.7".parse().unwrap()));
+    }
+
+    #[rocket::async_test]
+    async fn client_ip_forwarded() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let request = client
+            .get("/")
+            .remote(PROXY.parse::<SocketAddr>().unwrap())
+            .header(Header::new(
+                "Forwarded",
+                r#"for="[2001:db8::1]:4711", for=10.0.0.2:80"#,
+            ));
+
+        let ip = client_ip(request.inner(), &proxies());
+
+        assert_eq!(ip, Some("2001:db8::1".parse().unwrap()));
+    }
+
+    #[rocket::async_test]
+    async fn client_ip_forwarded_obfuscated() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let request = client
+            .get("/")
+            .remote(PROXY.parse::<SocketAddr>().unwrap())
+            .header(Header::new("Forwarded", "for=198.51.100.1, for=_hidden"));
+
+        let ip = client_ip(request.inner(), &proxies());
+
+        assert_eq!(ip, None);
+    }
+}
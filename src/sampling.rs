@@ -0,0 +1,228 @@
+//! Sample rates of transactions by request, from rules in the configuration or a callback.
+
+use std::sync::Arc;
+
+use regex::RegexSet;
+use rocket::serde::Deserialize;
+use rocket::Request;
+use sentry::{TracesSampler, TransactionContext};
+
+use crate::ignore::glob_to_regex;
+
+/// Key of the transaction context holding the sample rate chosen for the request.
+const SAMPLE_RATE_KEY: &str = "rocket_sentry.sample_rate";
+
+/// The sample rate of the transactions of matching requests, instead of
+/// `sentry_traces_sample_rate`.
+///
+/// Configured as an array of tables in `Rocket.toml`, where the first matching rule wins:
+///
+/// ```toml
+/// [[release.sentry.traces_sample_rules]]
+/// path = "/healthz"
+/// rate = 0.0
+///
+/// [[release.sentry.traces_sample_rules]]
+/// method = "POST"
+/// path = "/orders/<id>/**"
+/// rate = 1.0
+/// ```
+///
+/// Rules are matched before routing, so `path` is a glob of the request path, like the
+/// [ignored paths](crate::IgnoreConfig), rather than a route. Rocket's dynamic segments such as
+/// `<id>` match any segment of the path. A rule without `method` or `path` matches any.
+///
+/// Requests continuing an incoming trace keep its sampling decision, unless the matching rule
+/// sets `override_parent`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TracesSampleRule {
+    pub method: Option<String>,
+    pub path: Option<String>,
+    /// Share of matching transactions to send, between 0 and 1.
+    pub rate: f32,
+    /// Applies the rate to incoming traces too, instead of the decision of the upstream service.
+    #[serde(default)]
+    pub override_parent: bool,
+}
+
+/// Whether to send the transaction of a request, see
+/// [`RocketSentryBuilder::request_sampler`](crate::RocketSentryBuilder::request_sampler).
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum SamplingDecision {
+    /// Send the transaction with this probability, between 0 and 1. Rates out of that range are
+    /// logged and clamped.
+    Rate(f32),
+    /// Always send the transaction.
+    Keep,
+    /// Never send the transaction.
+    Drop,
+    /// Leave the decision to the sample rules, the `traces_sampler` and the
+    /// `sentry_traces_sample_rate`.
+    Defer,
+}
+
+impl SamplingDecision {
+    pub(crate) fn rate(self) -> Option<f32> {
+        match self {
+            SamplingDecision::Rate(rate) if (0.0..=1.0).contains(&rate) => Some(rate),
+            SamplingDecision::Rate(rate) => {
+                warn!("Sample rate {rate} of the request sampler isn't between 0 and 1.");
+                Some(if rate > 1.0 { 1.0 } else { 0.0 })
+            }
+            SamplingDecision::Keep => Some(1.0),
+            SamplingDecision::Drop => Some(0.0),
+            SamplingDecision::Defer => None,
+        }
+    }
+}
+
+/// Decides on the transaction of a request before it starts, see
+/// [`RocketSentryBuilder::request_sampler`](crate::RocketSentryBuilder::request_sampler).
+pub(crate) type RequestSampler = dyn Fn(&Request<'_>) -> SamplingDecision + Send + Sync;
+
+/// Compiled [`TracesSampleRule`]s.
+pub(crate) struct SampleRules {
+    paths: RegexSet,
+    rules: Vec<TracesSampleRule>,
+}
+
+impl SampleRules {
+    pub(crate) fn new(rules: &[TracesSampleRule]) -> Result<Self, regex::Error> {
+        let paths = rules
+            .iter()
+            .map(|rule| glob_to_regex(rule.path.as_deref().unwrap_or("**")));
+        Ok(SampleRules {
+            paths: RegexSet::new(paths)?,
+            rules: rules.to_vec(),
+        })
+    }
+
+    /// The rate of the first rule matching the request, which is the decision of the incoming
+    /// trace, if `sampled`, unless the rule overrides it.
+    pub(crate) fn rate(&self, request: &Request, sampled: Option<bool>) -> Option<f32> {
+        let method = request.method();
+        let matches = self.paths.matches(request.uri().path().as_str());
+        let rule = matches
+            .iter()
+            .map(|index| &self.rules[index])
+            .find(|rule| {
+                rule.method.as_ref().map_or(true, |rule_method| {
+                    rule_method.eq_ignore_ascii_case(method.as_str())
+                })
+            })?;
+        match sampled {
+            Some(sampled) if !rule.override_parent => Some(f32::from(sampled)),
+            _ => Some(rule.rate),
+        }
+    }
+}
+
+/// Hands the sample rate of a request over to the [`traces_sampler`].
+pub(crate) fn set_sample_rate(transaction_context: &mut TransactionContext, rate: f32) {
+    transaction_context.custom_insert(SAMPLE_RATE_KEY.to_string(), rate.into());
+}
+
+/// A sampler using the rate set for the request, falling back to the sampler of the builder or
+/// else the way Sentry samples: the decision of the incoming trace, then `traces_sample_rate`.
+pub(crate) fn traces_sampler(
+    fallback: Option<Arc<TracesSampler>>,
+    traces_sample_rate: f32,
+) -> Arc<TracesSampler> {
+    Arc::new(move |transaction_context: &TransactionContext| {
+        let rate = transaction_context
+            .custom()
+            .and_then(|custom| custom.get(SAMPLE_RATE_KEY))
+            .and_then(serde_json::Value::as_f64);
+        #[allow(clippy::cast_possible_truncation)] // Set from an f32
+        match (rate, &fallback) {
+            (Some(rate), _) => rate as f32,
+            (None, Some(fallback)) => fallback(transaction_context),
+            (None, None) => transaction_context
+                .sampled()
+                .map_or(traces_sample_rate, f32::from),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rocket::http::Method;
+    use rocket::local::asynchronous::Client;
+    use sentry::TransactionContext;
+
+    use super::{set_sample_rate, traces_sampler, SampleRules, SamplingDecision, TracesSampleRule};
+
+    fn rule(method: Option<&str>, path: Option<&str>, rate: f32) -> TracesSampleRule {
+        TracesSampleRule {
+            method: method.map(str::to_string),
+            path: path.map(str::to_string),
+            rate,
+            override_parent: false,
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rate() {
+        let rules = SampleRules::new(&[
+            rule(None, Some("/healthz"), 0.0),
+            rule(Some("post"), Some("/orders/<id>/**"), 1.0),
+            rule(Some("GET"), None, 0.5),
+        ])
+        .unwrap();
+        let client = Client::untracked(rocket::build()).await.unwrap();
+        let rate =
+            |method: Method, uri: &'static str| rules.rate(client.req(method, uri).inner(), None);
+
+        assert_eq!(rate(Method::Get, "/healthz"), Some(0.0)); // The first match wins
+        assert_eq!(rate(Method::Post, "/orders/42/items/1"), Some(1.0));
+        assert_eq!(rate(Method::Get, "/orders/42/items/1"), Some(0.5));
+        assert_eq!(rate(Method::Post, "/orders"), None);
+    }
+
+    #[rocket::async_test]
+    async fn rate_of_incoming_trace() {
+        let mut override_parent = rule(None, Some("/orders/**"), 0.0);
+        override_parent.override_parent = true;
+        let rules =
+            SampleRules::new(&[rule(None, Some("/healthz"), 0.0), override_parent]).unwrap();
+        let client = Client::untracked(rocket::build()).await.unwrap();
+        let rate = |uri: &'static str, sampled| rules.rate(client.get(uri).inner(), sampled);
+
+        assert_eq!(rate("/healthz", Some(true)), Some(1.0));
+        assert_eq!(rate("/healthz", Some(false)), Some(0.0));
+        assert_eq!(rate("/orders/42", Some(true)), Some(0.0));
+        assert_eq!(rate("/users", Some(true)), None);
+    }
+
+    #[test]
+    fn decision_rate() {
+        assert_eq!(SamplingDecision::Rate(0.3).rate(), Some(0.3));
+        assert_eq!(SamplingDecision::Rate(1.5).rate(), Some(1.0));
+        assert_eq!(SamplingDecision::Rate(-0.5).rate(), Some(0.0));
+        assert_eq!(SamplingDecision::Rate(f32::NAN).rate(), Some(0.0));
+        assert_eq!(SamplingDecision::Keep.rate(), Some(1.0));
+        assert_eq!(SamplingDecision::Drop.rate(), Some(0.0));
+        assert_eq!(SamplingDecision::Defer.rate(), None);
+    }
+
+    #[test]
+    fn sampler() {
+        let sampler = traces_sampler(None, 0.2);
+        let mut transaction_context = TransactionContext::new("GET /", "http.server");
+        assert!((sampler(&transaction_context) - 0.2).abs() < f32::EPSILON);
+
+        transaction_context.set_sampled(true);
+        assert!((sampler(&transaction_context) - 1.0).abs() < f32::EPSILON);
+
+        set_sample_rate(&mut transaction_context, 0.7);
+        assert!((sampler(&transaction_context) - 0.7).abs() < f32::EPSILON);
+
+        let sampler = traces_sampler(Some(Arc::new(|_: &TransactionContext| 0.4)), 0.2);
+        let transaction_context = TransactionContext::new("GET /", "http.server");
+        assert!((sampler(&transaction_context) - 0.4).abs() < f32::EPSILON);
+    }
+}
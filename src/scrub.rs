@@ -0,0 +1,340 @@
+//! Scrubbing of sensitive request data, such as credentials in headers and query strings, before
+//! it is sent to Sentry.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use regex::{RegexSet, RegexSetBuilder};
+use rocket::http::{CookieJar, RawStr};
+use rocket::serde::Deserialize;
+use serde_json::Value;
+
+/// Replaces the value of scrubbed headers and query parameters, so their presence is still visible.
+pub(crate) const FILTERED: &str = "[Filtered]";
+
+/// Header names matching these patterns are scrubbed unless `send_default_pii` is enabled.
+const DEFAULT_HEADER_PATTERNS: &[&str] = &[
+    "auth",
+    "cookie",
+    "token",
+    "secret",
+    "api-?key",
+    "session",
+    "csrf",
+    "xsrf",
+    // Client IP addresses
+    "^forwarded$",
+    "^x-forwarded-for$",
+    "^x-real-ip$",
+];
+
+/// Query parameter keys matching these patterns are scrubbed unless `send_default_pii` is enabled.
+const DEFAULT_QUERY_PARAM_PATTERNS: &[&str] = &[
+    "passw",
+    "secret",
+    "api_?key",
+    "auth",
+    "credential",
+    "token",
+    "session",
+    "signature",
+];
+
+/// Rules for scrubbing headers and query parameters, in addition to the built-in ones.
+///
+/// Names are matched case-insensitively; patterns are regular expressions matching anywhere in
+/// the name. Denied names are always scrubbed, while allowed names are exempt from the built-in
+/// rules. The built-in rules only apply while `send_default_pii` is disabled. The query parameter
+/// rules also apply to the fields of captured form and JSON request bodies.
+///
+/// Configured with a `sentry_scrub` table in `Rocket.toml`, for example:
+///
+/// ```toml
+/// [release.sentry_scrub]
+/// deny_headers = ["X-Tenant-Secret"]
+/// allow_headers = ["X-Session-Region"]
+/// deny_query_param_patterns = ["^card_"]
+/// allow_cookies = ["locale"]
+/// ```
+///
+/// Cookie values are only reported for cookies listed in `allow_cookies`, the other cookies are
+/// reported by name only. This takes the `secrets` feature, which tells Rocket's private cookies
+/// apart so that they are never reported; without it, no cookie values are reported at all.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ScrubConfig {
+    pub deny_headers: Vec<String>,
+    pub allow_headers: Vec<String>,
+    pub deny_header_patterns: Vec<String>,
+    pub deny_query_params: Vec<String>,
+    pub allow_query_params: Vec<String>,
+    pub deny_query_param_patterns: Vec<String>,
+    pub allow_cookies: Vec<String>,
+}
+
+/// Compiled [`ScrubConfig`].
+pub(crate) struct Scrubber {
+    headers: NameFilter,
+    query_params: NameFilter,
+    allow_cookies: HashSet<String>,
+    /// Client IP addresses are personal data, only sent with `send_default_pii`
+    scrub_client_ip: bool,
+}
+
+impl Scrubber {
+    pub(crate) fn new(config: &ScrubConfig, send_default_pii: bool) -> Result<Self, regex::Error> {
+        if cfg!(not(feature = "secrets")) && !config.allow_cookies.is_empty() {
+            warn!(
+                "Sentry `allow_cookies` takes the `secrets` feature of rocket-sentry, ignoring it."
+            );
+        }
+        let (default_headers, default_query_params): (&[&str], &[&str]) = if send_default_pii {
+            (&[], &[])
+        } else {
+            (DEFAULT_HEADER_PATTERNS, DEFAULT_QUERY_PARAM_PATTERNS)
+        };
+        Ok(Scrubber {
+            headers: NameFilter::new(
+                &config.deny_headers,
+                &config.allow_headers,
+                &config.deny_header_patterns,
+                default_headers,
+            )?,
+            query_params: NameFilter::new(
+                &config.deny_query_params,
+                &config.allow_query_params,
+                &config.deny_query_param_patterns,
+                default_query_params,
+            )?,
+            allow_cookies: config.allow_cookies.iter().cloned().collect(),
+            scrub_client_ip: !send_default_pii,
+        })
+    }
+
+    pub(crate) fn header_value<'a>(&self, name: &str, value: &'a str) -> &'a str {
+        if self.headers.is_denied(name) {
+            FILTERED
+        } else {
+            value
+        }
+    }
+
+    /// Replaces the values of scrubbed parameters, keeping the query string otherwise intact.
+    pub(crate) fn query_string(&self, query: &str) -> String {
+        query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) if self.query_param_denied(key) => format!("{key}={FILTERED}"),
+                // A parameter without a value may be the credential itself, as in `?token`
+                None if self.query_param_denied(pair) => FILTERED.to_string(),
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    pub(crate) fn client_ip(&self, ip: Option<IpAddr>) -> Option<IpAddr> {
+        if self.scrub_client_ip {
+            None
+        } else {
+            ip
+        }
+    }
+
+    /// Replaces the values of scrubbed fields in JSON objects, at any depth.
+    pub(crate) fn json(&self, value: &mut Value) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    if self.query_params.is_denied(key) {
+                        *value = Value::String(FILTERED.to_string());
+                    } else {
+                        self.json(value);
+                    }
+                }
+            }
+            Value::Array(array) => array.iter_mut().for_each(|value| self.json(value)),
+            _ => {}
+        }
+    }
+
+    /// Formats the request cookies like a `Cookie` header, with the values of cookies that aren't
+    /// explicitly allowed replaced.
+    pub(crate) fn cookies(&self, cookies: &CookieJar) -> Option<String> {
+        let mut cookies: Vec<_> = cookies
+            .iter()
+            .map(|cookie| {
+                let name = cookie.name();
+                if self.allow_cookies.contains(name) && !is_private(cookies, name) {
+                    format!("{name}={}", cookie.value())
+                } else {
+                    format!("{name}={FILTERED}")
+                }
+            })
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        cookies.sort();
+        Some(cookies.join("; "))
+    }
+
+    fn query_param_denied(&self, key: &str) -> bool {
+        let key = RawStr::new(key).url_decode_lossy();
+        self.query_params.is_denied(&key)
+    }
+}
+
+#[cfg(feature = "secrets")]
+fn is_private(cookies: &CookieJar, name: &str) -> bool {
+    cookies.get_private(name).is_some()
+}
+
+#[cfg(not(feature = "secrets"))]
+fn is_private(_cookies: &CookieJar, _name: &str) -> bool {
+    // The application may have enabled Rocket's `secrets` feature itself, and without it here
+    // there is no telling its private cookies apart
+    true
+}
+
+impl Default for Scrubber {
+    fn default() -> Self {
+        Scrubber::new(&ScrubConfig::default(), false).expect("Built-in patterns are valid")
+    }
+}
+
+struct NameFilter {
+    deny: HashSet<String>,
+    allow: HashSet<String>,
+    deny_patterns: RegexSet,
+    default_patterns: RegexSet,
+}
+
+impl NameFilter {
+    fn new(
+        deny: &[String],
+        allow: &[String],
+        deny_patterns: &[String],
+        default_patterns: &[&str],
+    ) -> Result<Self, regex::Error> {
+        let lowercase = |names: &[String]| names.iter().map(|n| n.to_lowercase()).collect();
+        let compile = |patterns: &[&str]| {
+            RegexSetBuilder::new(patterns)
+                .case_insensitive(true)
+                .build()
+        };
+        let deny_patterns: Vec<&str> = deny_patterns.iter().map(String::as_str).collect();
+        Ok(NameFilter {
+            deny: lowercase(deny),
+            allow: lowercase(allow),
+            deny_patterns: compile(&deny_patterns)?,
+            default_patterns: compile(default_patterns)?,
+        })
+    }
+
+    fn is_denied(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        if self.deny.contains(&name) || self.deny_patterns.is_match(&name) {
+            return true;
+        }
+        !self.allow.contains(&name) && self.default_patterns.is_match(&name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScrubConfig, Scrubber, FILTERED};
+
+    #[test]
+    fn default_headers() {
+        let scrubber = Scrubber::default();
+
+        assert_eq!(scrubber.header_value("Authorization", "Bearer x"), FILTERED);
+        assert_eq!(scrubber.header_value("Cookie", "a=b"), FILTERED);
+        assert_eq!(scrubber.header_value("X-Api-Key", "x"), FILTERED);
+        assert_eq!(
+            scrubber.header_value("Content-Type", "text/plain"),
+            "text/plain"
+        );
+    }
+
+    #[test]
+    fn send_default_pii_disables_defaults() {
+        let scrubber = Scrubber::new(&ScrubConfig::default(), true).unwrap();
+
+        assert_eq!(
+            scrubber.header_value("Authorization", "Bearer x"),
+            "Bearer x"
+        );
+        assert_eq!(scrubber.query_string("token=x"), "token=x");
+    }
+
+    #[test]
+    fn custom_rules() {
+        let config = ScrubConfig {
+            deny_headers: vec!["X-Tenant".to_string()],
+            allow_headers: vec!["x-session-region".to_string()],
+            deny_query_param_patterns: vec!["^card_".to_string()],
+            ..Default::default()
+        };
+        let scrubber = Scrubber::new(&config, true).unwrap();
+
+        assert_eq!(scrubber.header_value("x-tenant", "acme"), FILTERED);
+        assert_eq!(scrubber.header_value("X-Session-Region", "eu"), "eu");
+        assert_eq!(
+            scrubber.query_string("card_number=1&discard_x=2"),
+            format!("card_number={FILTERED}&discard_x=2")
+        );
+    }
+
+    #[test]
+    fn allow_overrides_defaults_only() {
+        let config = ScrubConfig {
+            allow_headers: vec!["X-Session-Region".to_string()],
+            deny_header_patterns: vec!["region".to_string()],
+            ..Default::default()
+        };
+        let scrubber = Scrubber::new(&config, false).unwrap();
+
+        assert_eq!(scrubber.header_value("X-Session-Region", "eu"), FILTERED);
+    }
+
+    #[test]
+    fn query_string() {
+        let scrubber = Scrubber::default();
+
+        assert_eq!(
+            scrubber.query_string("page=2&access_token=abc&flag&Pass%77ord=x&token"),
+            format!("page=2&access_token={FILTERED}&flag&Pass%77ord={FILTERED}&{FILTERED}")
+        );
+    }
+
+    #[test]
+    fn json() {
+        let scrubber = Scrubber::default();
+        let mut value = serde_json::json!({
+            "user": {"name": "a", "password": "b"},
+            "items": [{"token": "c"}],
+        });
+
+        scrubber.json(&mut value);
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "user": {"name": "a", "password": FILTERED},
+                "items": [{"token": FILTERED}],
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let config = ScrubConfig {
+            deny_header_patterns: vec!["(".to_string()],
+            ..Default::default()
+        };
+
+        assert!(Scrubber::new(&config, false).is_err());
+    }
+}
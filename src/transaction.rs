@@ -0,0 +1,131 @@
+//! Access to the request transaction from route handlers.
+
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use sentry::protocol::{SpanStatus, Value};
+use sentry::{Hub, Span, Transaction, TransactionContext};
+
+use crate::{get_current_transaction, TRANSACTION_SOURCE_KEY};
+
+/// The transaction of the current request, as a request guard.
+///
+/// Handlers can use it to start child spans and to add tags and data. The name and status set
+/// through it take precedence over the ones the fairing sets when the response is sent.
+///
+/// ```no_run
+/// # #[macro_use]
+/// # extern crate rocket;
+/// use rocket_sentry::SentryTransaction;
+///
+/// #[get("/report")]
+/// fn report(transaction: SentryTransaction<'_>) -> &'static str {
+///     let span = transaction.start_child("db.query", "SELECT * FROM reports");
+///     // Query the database...
+///     span.finish();
+///     transaction.set_tag("report.kind", "monthly");
+///     "Report"
+/// }
+/// ```
+///
+/// The guard never fails. If the request has no transaction, because Sentry or transactions are
+/// disabled, see `sentry_traces_sample_rate`, or the request is [ignored](crate::IgnoreConfig),
+/// its methods do nothing and child spans aren't sent.
+#[derive(Clone, Copy)]
+pub struct SentryTransaction<'r> {
+    transaction: Option<&'r Transaction>,
+    overrides: &'r TransactionOverrides,
+}
+
+/// Changes made by handlers that the fairing must not overwrite.
+#[derive(Default)]
+pub(crate) struct TransactionOverrides {
+    name: Mutex<Option<String>>,
+    status: Mutex<Option<SpanStatus>>,
+}
+
+impl SentryTransaction<'_> {
+    /// Starts a child span, which is sent along with the transaction once finished.
+    #[must_use]
+    pub fn start_child(&self, op: &str, description: &str) -> Span {
+        match self.transaction {
+            Some(transaction) => transaction.start_child(op, description),
+            // A transaction without a client isn't sampled, and neither are its spans
+            None => Hub::new(None, Arc::default())
+                .start_transaction(TransactionContext::new(op, op))
+                .start_child(op, description),
+        }
+    }
+
+    /// Sets a tag, for searching transactions in Sentry.
+    pub fn set_tag<V: ToString>(&self, key: &str, value: V) {
+        if let Some(transaction) = self.transaction {
+            transaction.set_tag(key, value);
+        }
+    }
+
+    /// Sets arbitrary data on the transaction.
+    pub fn set_data(&self, key: &str, value: Value) {
+        if let Some(transaction) = self.transaction {
+            transaction.set_data(key, value);
+        }
+    }
+
+    /// Sets the status, instead of the one derived from the response status.
+    pub fn set_status(&self, status: SpanStatus) {
+        if let Some(transaction) = self.transaction {
+            *lock(&self.overrides.status) = Some(status);
+            transaction.set_status(status);
+        }
+    }
+
+    /// Renames the transaction, instead of naming it after the matched route.
+    pub fn set_name(&self, name: &str) {
+        if let Some(transaction) = self.transaction {
+            *lock(&self.overrides.name) = Some(name.to_string());
+            set_custom_name(transaction, name);
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SentryTransaction<'r> {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Infallible> {
+        Outcome::Success(SentryTransaction {
+            transaction: get_current_transaction(request),
+            overrides: request.local_cache(Arc::<TransactionOverrides>::default),
+        })
+    }
+}
+
+/// Reapplies the name and status set by the handler, after the fairing set its own.
+pub(crate) fn apply_overrides(request: &Request, transaction: &Transaction) {
+    request
+        .local_cache(Arc::<TransactionOverrides>::default)
+        .apply(transaction);
+}
+
+impl TransactionOverrides {
+    pub(crate) fn apply(&self, transaction: &Transaction) {
+        if let Some(name) = lock(&self.name).as_deref() {
+            set_custom_name(transaction, name);
+        }
+        if let Some(status) = *lock(&self.status) {
+            transaction.set_status(status);
+        }
+    }
+}
+
+fn set_custom_name(transaction: &Transaction, name: &str) {
+    transaction.set_name(name);
+    transaction.set_data(TRANSACTION_SOURCE_KEY, "custom".into());
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // The values stay consistent even if a handler panicked while holding the lock
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,86 @@
+//! Identification of the user making a request.
+
+use rocket::Request;
+use sentry::User;
+
+/// Identifies the user making a request, so that events and transactions show who is affected.
+///
+/// Runs for every request before it is routed, and the user is set on the request's scope.
+/// That includes requests for routes that don't need a user, so keep it cheap: request guards
+/// run here run again in the handler, unless they cache their outcome with
+/// [`Request::local_cache`]. Implement this to identify users with request guards:
+///
+/// ```no_run
+/// # #[macro_use]
+/// # extern crate rocket;
+/// use rocket::http::Status;
+/// use rocket::request::{self, FromRequest, Request};
+/// use rocket_sentry::{RocketSentry, UserIdentifier};
+///
+/// struct ApiUser(String);
+///
+/// #[rocket::async_trait]
+/// impl<'r> FromRequest<'r> for ApiUser {
+///     type Error = ();
+///
+///     async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+///         match request.cookies().get("user_id") {
+///             Some(cookie) => request::Outcome::Success(ApiUser(cookie.value().to_string())),
+///             None => request::Outcome::Error((Status::Unauthorized, ())),
+///         }
+///     }
+/// }
+///
+/// struct ApiUserIdentifier;
+///
+/// #[rocket::async_trait]
+/// impl UserIdentifier for ApiUserIdentifier {
+///     async fn identify(&self, request: &Request<'_>) -> Option<sentry::User> {
+///         let ApiUser(id) = request.guard::<ApiUser>().await.succeeded()?;
+///         Some(sentry::User {
+///             id: Some(id),
+///             ..Default::default()
+///         })
+///     }
+/// }
+///
+/// # fn main() {
+/// #[launch]
+/// fn rocket() -> _ {
+///     rocket::build().attach(
+///         RocketSentry::builder()
+///             .user_identifier(ApiUserIdentifier)
+///             .build(),
+///     )
+/// }
+/// # }
+/// ```
+///
+/// Or with a closure, for users identified without request guards:
+///
+/// ```
+/// use rocket::Request;
+/// use rocket_sentry::RocketSentry;
+///
+/// RocketSentry::builder().user_identifier(|request: &Request<'_>| {
+///     let id = request.cookies().get("user_id")?.value().to_string();
+///     Some(sentry::User {
+///         id: Some(id),
+///         ..Default::default()
+///     })
+/// });
+/// ```
+#[rocket::async_trait]
+pub trait UserIdentifier: Send + Sync + 'static {
+    async fn identify(&self, request: &Request<'_>) -> Option<User>;
+}
+
+#[rocket::async_trait]
+impl<F> UserIdentifier for F
+where
+    F: Fn(&Request<'_>) -> Option<User> + Send + Sync + 'static,
+{
+    async fn identify(&self, request: &Request<'_>) -> Option<User> {
+        self(request)
+    }
+}
@@ -0,0 +1,51 @@
+//! Tests reading the standard Sentry environment variables.
+//!
+//! These are process-wide, so they live apart from the other tests, which would see them too.
+
+use figment::Figment;
+use rocket::{Config, Ignite, Rocket};
+use rocket_sentry::{RocketSentry, SentryHandle};
+
+const SENTRY_DSN_CONFIG: (&str, &str) = ("sentry_dsn", "https://123@sentry.io/456");
+/// Rocket won't launch in release without one when the `secrets` feature is enabled.
+const SECRET_KEY_CONFIG: (&str, &str) =
+    ("secret_key", "hPRYyVRiMyxpw5sBB1XeCMN1kFsDCqKvBi2QJxBVHQk=");
+
+fn environment(rocket: &Rocket<Ignite>) -> String {
+    let hub = rocket.state::<SentryHandle>().unwrap().hub().unwrap();
+    let options = hub.client().unwrap().options().clone();
+    options.environment.unwrap().into_owned()
+}
+
+/// `SENTRY_ENVIRONMENT` beats the default of the profile, but not the application's settings
+#[rocket::async_test]
+async fn fairing_init_with_sentry_environment_variable() {
+    std::env::set_var("SENTRY_ENVIRONMENT", "qa");
+    let figment = Figment::from(Config::release_default())
+        .join(SENTRY_DSN_CONFIG)
+        .join(SECRET_KEY_CONFIG);
+    let ignite = |figment: Figment, rocket_sentry: RocketSentry| async move {
+        rocket::custom(figment)
+            .attach(rocket_sentry)
+            .ignite()
+            .await
+            .expect("Rocket failed to ignite")
+    };
+
+    let rocket = ignite(figment.clone(), RocketSentry::builder().build()).await;
+    assert_eq!(environment(&rocket), "qa");
+
+    let rocket_sentry = RocketSentry::builder()
+        .profile_environment("release", "staging")
+        .build();
+    let rocket = ignite(figment.clone(), rocket_sentry).await;
+    assert_eq!(environment(&rocket), "staging");
+
+    let mapped = figment.clone().join(("sentry.environments.release", "eu"));
+    let rocket = ignite(mapped, RocketSentry::builder().build()).await;
+    assert_eq!(environment(&rocket), "eu");
+
+    let explicit = figment.join(("sentry_environment", "canary"));
+    let rocket = ignite(explicit, RocketSentry::builder().build()).await;
+    assert_eq!(environment(&rocket), "canary");
+}
@@ -0,0 +1,917 @@
+//! Tests dispatching requests through the fairing.
+//!
+//! These live apart from `tests.rs`, which checks the hub state before and after initializing Sentry.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use figment::providers::{Format, Toml};
+use figment::Figment;
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::request::{self, FromRequest};
+use rocket::tokio::sync::Notify;
+use rocket::{Config, Request, State};
+use rocket_sentry::{
+    RocketSentry, SamplingDecision, SentryHandle, SentryTransaction, UserIdentifier,
+};
+use sentry::protocol::{Breadcrumb, Context, EnvelopeItem, Event, Frame, SpanStatus, Stacktrace};
+use sentry::types::Dsn;
+use sentry::{ClientOptions, Envelope, Hub, Transport, TransportFactory, TransportOptions};
+
+const SENTRY_DSN_CONFIG: (&str, &str) = ("sentry_dsn", "https://123@sentry.io/456");
+
+#[rocket::get("/")]
+fn index() -> &'static str {
+    "Hello"
+}
+
+#[rocket::async_test]
+async fn fairing_emits_w3c_trace_response_headers() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0))
+        .merge(("sentry_trace_propagation", "w3c"))
+        .merge(("sentry_trace_response_headers", true));
+    let rocket = rocket::custom(figment)
+        .attach(RocketSentry::fairing())
+        .mount("/", rocket::routes![index]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = client
+        .get("/")
+        .header(Header::new(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        ))
+        .header(Header::new("tracestate", "vendor=value"))
+        .dispatch()
+        .await;
+
+    let traceparent = response.headers().get_one("traceparent").unwrap();
+    assert!(traceparent.starts_with("00-0af7651916cd43dd8448eb211c80319c-"));
+    assert!(traceparent.ends_with("-01"));
+    assert_eq!(
+        response.headers().get_one("tracestate"),
+        Some("vendor=value")
+    );
+    assert_eq!(response.headers().get_one("sentry-trace"), None);
+}
+
+#[rocket::get("/trace")]
+fn trace() -> String {
+    let span = Hub::current().configure_scope(|scope| scope.get_span());
+    span.map(|span| span.get_trace_context().trace_id.to_string())
+        .unwrap_or_default()
+}
+
+/// Each request's handler sees its own transaction through `Hub::current()`, also without
+/// `bind_hub` while requests don't run concurrently
+#[rocket::async_test]
+async fn fairing_binds_request_hub() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0));
+    let rocket = rocket::custom(figment)
+        .attach(RocketSentry::fairing())
+        .mount("/", rocket_sentry::bind_hub(rocket::routes![trace]))
+        .mount("/unbound", rocket::routes![trace]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    for (uri, trace_id) in [
+        ("/trace", "09e04486820349518ac7b5d2adbf6ba5"),
+        ("/trace", "0af7651916cd43dd8448eb211c80319c"),
+        ("/unbound/trace", "4bf92f3577b34da6a3ce929d0e0e4736"),
+    ] {
+        let response = client
+            .get(uri)
+            .header(Header::new(
+                "sentry-trace",
+                format!("{trace_id}-9cf635fa5b870b3a-1"),
+            ))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.into_string().await.unwrap(), trace_id);
+    }
+}
+
+/// Looking up the request hub before the fairing's `on_request` doesn't keep it from being set
+#[rocket::async_test]
+async fn fairing_binds_request_hub_after_earlier_lookup() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0));
+    let lookup = AdHoc::on_request("Lookup", |request, _| {
+        Box::pin(async move {
+            let handle = request.rocket().state::<SentryHandle>().unwrap();
+            let _ = handle.request_hub(request);
+        })
+    });
+    let rocket = rocket::custom(figment)
+        .attach(lookup)
+        .attach(RocketSentry::fairing())
+        .mount("/", rocket_sentry::bind_hub(rocket::routes![trace]));
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let trace_id = "09e04486820349518ac7b5d2adbf6ba5";
+    let response = client
+        .get("/trace")
+        .header(Header::new(
+            "sentry-trace",
+            format!("{trace_id}-9cf635fa5b870b3a-1"),
+        ))
+        .dispatch()
+        .await;
+
+    assert_eq!(response.into_string().await.unwrap(), trace_id);
+}
+
+#[rocket::get("/users/<id>?<q>")]
+fn scoped_event(id: u32, q: Option<&str>) -> String {
+    let _ = (id, q);
+    let event = Hub::current()
+        .configure_scope(|scope| scope.apply_to_event(Event::default()))
+        .unwrap();
+    let request = event.request.unwrap();
+    let Some(Context::Other(route)) = event.contexts.get("route") else {
+        panic!("No route context");
+    };
+    format!(
+        "{} | {} {} | {}",
+        event.transaction.unwrap(),
+        request.method.unwrap(),
+        request.query_string.unwrap(),
+        route["name"],
+    )
+}
+
+/// Events captured within a handler carry the request and route
+#[rocket::async_test]
+async fn fairing_sets_request_on_scope_events() {
+    let figment = Figment::from(Config::debug_default()).merge(SENTRY_DSN_CONFIG);
+    let rocket = rocket::custom(figment)
+        .attach(RocketSentry::fairing())
+        .mount("/", rocket_sentry::bind_hub(rocket::routes![scoped_event]));
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = client.get("/users/6?q=x").dispatch().await;
+
+    assert_eq!(
+        response.into_string().await.unwrap(),
+        r#"GET /users/<id> | GET q=x | "scoped_event""#
+    );
+}
+
+#[rocket::post("/login", data = "<body>")]
+fn login(body: &str) -> String {
+    let event = Hub::current()
+        .configure_scope(|scope| scope.apply_to_event(Event::default()))
+        .unwrap();
+    format!("{} | {body}", event.request.unwrap().data.unwrap())
+}
+
+/// Captured request bodies are scrubbed, and still available to the handler
+#[rocket::async_test]
+async fn fairing_captures_request_body() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_max_request_body_size", 512));
+    let rocket = rocket::custom(figment)
+        .attach(RocketSentry::fairing())
+        .mount("/", rocket_sentry::bind_hub(rocket::routes![login]));
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = client
+        .post("/login")
+        .header(ContentType::Form)
+        .body("user=a&password=b")
+        .dispatch()
+        .await;
+
+    assert_eq!(
+        response.into_string().await.unwrap(),
+        "user=a&password=[Filtered] | user=a&password=b"
+    );
+}
+
+struct ApiUser(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        match request.headers().get_one("X-Api-User") {
+            Some(id) => request::Outcome::Success(ApiUser(id.to_string())),
+            None => request::Outcome::Forward(rocket::http::Status::Unauthorized),
+        }
+    }
+}
+
+struct ApiUserIdentifier;
+
+#[rocket::async_trait]
+impl UserIdentifier for ApiUserIdentifier {
+    async fn identify(&self, request: &Request<'_>) -> Option<sentry::User> {
+        let ApiUser(id) = request.guard::<ApiUser>().await.succeeded()?;
+        Some(sentry::User {
+            id: Some(id),
+            ..Default::default()
+        })
+    }
+}
+
+#[rocket::get("/me")]
+fn me() -> String {
+    let event = Hub::current()
+        .configure_scope(|scope| scope.apply_to_event(Event::default()))
+        .unwrap();
+    event.user.and_then(|user| user.id).unwrap_or_default()
+}
+
+/// Events captured within a handler carry the user identified from the request
+#[rocket::async_test]
+async fn fairing_identifies_user() {
+    let figment = Figment::from(Config::debug_default()).merge(SENTRY_DSN_CONFIG);
+    let rocket = rocket::custom(figment)
+        .attach(
+            RocketSentry::builder()
+                .user_identifier(ApiUserIdentifier)
+                .build(),
+        )
+        .mount("/", rocket_sentry::bind_hub(rocket::routes![me]));
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = client
+        .get("/me")
+        .header(Header::new("X-Api-User", "42"))
+        .dispatch()
+        .await;
+    assert_eq!(response.into_string().await.unwrap(), "42");
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.into_string().await.unwrap(), "");
+}
+
+/// Client options come from the `sentry` table, which takes precedence over `sentry_` keys
+#[rocket::async_test]
+async fn fairing_init_with_sentry_table() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(("sentry_dsn", ""))
+        .merge(("sentry_release", "flat@1.0.0"))
+        .merge(("sentry.dsn", "https://123@sentry.io/456"))
+        .merge(("sentry.release", "app@1.0.0"))
+        .merge(("sentry.max_breadcrumbs", 10))
+        .merge(("sentry.in_app_include", ["app"]))
+        .merge(("sentry.tags.region", "eu"));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .build();
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry)
+        .mount("/", rocket_sentry::bind_hub(rocket::routes![tagged]));
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = client.get("/tagged").dispatch().await;
+    assert_eq!(response.into_string().await.unwrap(), "eu");
+
+    let hub = client
+        .rocket()
+        .state::<SentryHandle>()
+        .unwrap()
+        .hub()
+        .unwrap();
+    let frame = |function: &str| Frame {
+        function: Some(function.to_string()),
+        ..Default::default()
+    };
+    for _ in 0..12 {
+        hub.add_breadcrumb(Breadcrumb::default());
+    }
+    hub.capture_event(Event {
+        stacktrace: Some(Stacktrace {
+            frames: vec![frame("app::handler"), frame("other::helper")],
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    let event = recorder.events().pop().unwrap();
+    assert_eq!(event.release.as_deref(), Some("app@1.0.0"));
+    assert_eq!(event.breadcrumbs.len(), 10);
+    let frames = &event.stacktrace.unwrap().frames;
+    assert_eq!(frames[0].in_app, Some(true));
+    assert_eq!(frames[1].in_app, None);
+}
+
+#[rocket::get("/tagged")]
+fn tagged() -> String {
+    let event = Hub::current()
+        .configure_scope(|scope| scope.apply_to_event(Event::default()))
+        .unwrap();
+    event.tags["region"].clone()
+}
+
+/// The client options can be adjusted after they are read from the Rocket configuration
+#[rocket::async_test]
+async fn fairing_init_with_client_options() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry.release", "app@1.0.0"));
+    let recorder = Arc::new(Recorder::default());
+    let record = record_to(&recorder);
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(move |options| {
+            assert_eq!(options.release.as_deref(), Some("app@1.0.0"));
+            options.max_breadcrumbs = 3;
+            record(options);
+        })
+        .build();
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry)
+        .ignite()
+        .await
+        .expect("Rocket failed to ignite");
+
+    let hub = rocket.state::<SentryHandle>().unwrap().hub().unwrap();
+    for _ in 0..5 {
+        hub.add_breadcrumb(Breadcrumb::default());
+    }
+    hub.capture_message("Configured", sentry::Level::Info);
+    let event = recorder.events().pop().unwrap();
+    assert_eq!(event.breadcrumbs.len(), 3);
+    assert_eq!(event.environment.as_deref(), Some("development"));
+}
+
+/// Records the envelopes that would be sent to Sentry, whether they were flushed, and the DSN they
+/// would be sent to when set up through [`record_to`]
+#[derive(Default)]
+struct Recorder(Mutex<Vec<Envelope>>, AtomicBool, Mutex<Option<Dsn>>);
+
+impl Transport for Recorder {
+    fn send_envelope(&self, envelope: Envelope) {
+        self.0.lock().unwrap().push(envelope);
+    }
+
+    fn flush(&self, _timeout: Duration) -> bool {
+        self.1.store(true, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Sends to the recorder, and has it record the DSN
+fn record_to(recorder: &Arc<Recorder>) -> impl Fn(&mut ClientOptions) + Send + Sync + 'static {
+    let recorder = recorder.clone();
+    move |options| options.transport = Some(Arc::new(RecorderFactory(recorder.clone())))
+}
+
+struct RecorderFactory(Arc<Recorder>);
+
+impl TransportFactory for RecorderFactory {
+    fn create_transport_with_options(&self, options: TransportOptions) -> Arc<dyn Transport> {
+        *self.0 .2.lock().unwrap() = Some(options.dsn);
+        self.0.clone()
+    }
+}
+
+impl Recorder {
+    /// Copies of the recorded events, so assertions don't hold the lock: the panic of a failing
+    /// assertion is captured too
+    fn events(&self) -> Vec<Event<'static>> {
+        let envelopes = self.0.lock().unwrap();
+        envelopes
+            .iter()
+            .filter_map(Envelope::event)
+            .cloned()
+            .collect()
+    }
+
+    fn transaction_names(&self) -> Vec<String> {
+        let envelopes = self.0.lock().unwrap();
+        envelopes
+            .iter()
+            .flat_map(Envelope::items)
+            .filter_map(|item| match item {
+                EnvelopeItem::Transaction(transaction) => transaction.name.clone(),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[rocket::get("/capture")]
+fn capture() -> &'static str {
+    sentry::capture_message("Captured", sentry::Level::Info);
+    "Captured"
+}
+
+#[rocket::get("/health")]
+fn health() -> &'static str {
+    "OK"
+}
+
+/// User callbacks filter events and transactions, and see the request they belong to
+#[rocket::async_test]
+async fn fairing_runs_before_send_callbacks() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .before_send(|mut event, request| {
+            let tenant = request?.headers.get_one("X-Tenant")?;
+            event.tags.insert("tenant".to_string(), tenant.to_string());
+            Some(event)
+        })
+        .before_send_transaction(|mut transaction, request| {
+            let request = request?;
+            if request.path == "/health" {
+                return None;
+            }
+            transaction
+                .tags
+                .insert("method".to_string(), request.method.to_string());
+            Some(transaction)
+        })
+        .build();
+    let rocket = rocket::custom(figment).attach(rocket_sentry).mount(
+        "/",
+        rocket_sentry::bind_hub(rocket::routes![capture, health]),
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    client.get("/health").dispatch().await;
+    client
+        .get("/capture")
+        .header(Header::new("X-Tenant", "acme"))
+        .dispatch()
+        .await;
+    // Without the tenant header, before_send drops the event
+    client.get("/capture").dispatch().await;
+
+    let envelopes = recorder.0.lock().unwrap();
+    let events: Vec<_> = envelopes.iter().filter_map(Envelope::event).collect();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].tags["tenant"], "acme");
+    let transactions: Vec<_> = envelopes
+        .iter()
+        .flat_map(Envelope::items)
+        .filter_map(|item| match item {
+            EnvelopeItem::Transaction(transaction) => Some(transaction),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(transactions.len(), 2);
+    for transaction in transactions {
+        assert_eq!(transaction.name.as_deref(), Some("GET /capture"));
+        assert_eq!(transaction.tags["method"], "GET");
+    }
+}
+
+/// The DSN can be read from a file, such as a mounted secret
+#[rocket::async_test]
+async fn fairing_init_with_dsn_file() {
+    let path = std::env::temp_dir().join("rocket-sentry-dsn-file");
+    std::fs::write(&path, "https://789@sentry.io/456\n").unwrap();
+    let figment = Figment::from(Config::debug_default()).merge(("sentry_dsn_file", &path));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .build();
+    rocket::custom(figment)
+        .attach(rocket_sentry)
+        .ignite()
+        .await
+        .expect("Rocket failed to ignite");
+    std::fs::remove_file(&path).unwrap();
+
+    let dsn = recorder.2.lock().unwrap().clone().unwrap();
+    assert_eq!(dsn.public_key(), "789");
+}
+
+/// Lets a test shut down while a request is in flight
+#[derive(Default)]
+struct Pause {
+    started: Notify,
+    resume: Notify,
+}
+
+#[rocket::get("/slow")]
+async fn slow(pause: &State<Arc<Pause>>) -> &'static str {
+    pause.started.notify_one();
+    pause.resume.notified().await;
+    "Done"
+}
+
+/// Shutting down aborts the transactions of requests still in flight once Rocket's grace and
+/// mercy periods are over, and flushes them
+#[rocket::async_test]
+async fn fairing_aborts_transactions_on_shutdown() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0))
+        .merge(("shutdown.grace", 0))
+        .merge(("shutdown.mercy", 0));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = Arc::new(
+        RocketSentry::builder()
+            .client_options(record_to(&recorder))
+            .build(),
+    );
+    let pause = Arc::new(Pause::default());
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry.clone())
+        .manage(pause.clone())
+        .mount("/", rocket::routes![slow]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let request = client.get("/slow").dispatch();
+    let shutdown = async {
+        pause.started.notified().await;
+        rocket_sentry.on_shutdown(client.rocket()).await;
+        pause.resume.notify_one();
+    };
+    let (response, ()) = rocket::tokio::join!(request, shutdown);
+    assert_eq!(response.into_string().await.unwrap(), "Done");
+
+    let envelopes = recorder.0.lock().unwrap();
+    let transactions: Vec<_> = envelopes
+        .iter()
+        .flat_map(Envelope::items)
+        .filter_map(|item| match item {
+            EnvelopeItem::Transaction(transaction) => Some(transaction),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(transactions.len(), 1); // Not sent again when the request completes
+    assert_eq!(transactions[0].name.as_deref(), Some("GET /slow"));
+    let method = transactions[0]
+        .request
+        .as_ref()
+        .and_then(|request| request.method.as_deref());
+    assert_eq!(method, Some("GET"));
+    let trace = transactions[0].contexts.get("trace");
+    assert!(matches!(
+        trace,
+        Some(Context::Trace(trace)) if trace.status == Some(SpanStatus::Aborted)
+    ));
+    assert!(recorder.1.load(Ordering::Relaxed));
+}
+
+/// Requests that respond during Rocket's grace period finish their transactions as usual
+#[rocket::async_test]
+async fn fairing_waits_for_requests_on_shutdown() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = Arc::new(
+        RocketSentry::builder()
+            .client_options(record_to(&recorder))
+            .build(),
+    );
+    let pause = Arc::new(Pause::default());
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry.clone())
+        .manage(pause.clone())
+        .mount("/", rocket::routes![slow]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let request = client.get("/slow").dispatch();
+    let shutdown = async {
+        pause.started.notified().await;
+        let resume = async { pause.resume.notify_one() };
+        rocket::tokio::join!(rocket_sentry.on_shutdown(client.rocket()), resume);
+    };
+    let (response, ()) = rocket::tokio::join!(request, shutdown);
+    assert_eq!(response.into_string().await.unwrap(), "Done");
+
+    let envelopes = recorder.0.lock().unwrap();
+    let transactions: Vec<_> = envelopes
+        .iter()
+        .flat_map(Envelope::items)
+        .filter_map(|item| match item {
+            EnvelopeItem::Transaction(transaction) => Some(transaction),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(transactions.len(), 1);
+    let trace = transactions[0].contexts.get("trace");
+    assert!(matches!(
+        trace,
+        Some(Context::Trace(trace)) if trace.status == Some(SpanStatus::Ok)
+    ));
+    assert!(recorder.1.load(Ordering::Relaxed));
+}
+
+#[rocket::get("/report")]
+fn report(transaction: SentryTransaction<'_>) -> &'static str {
+    transaction.start_child("db.query", "SELECT").finish();
+    transaction.set_tag("report.kind", "monthly");
+    transaction.set_name("report");
+    transaction.set_status(SpanStatus::DeadlineExceeded);
+    "Report"
+}
+
+/// Handlers can change the request transaction through the request guard
+#[rocket::async_test]
+async fn fairing_provides_transaction_guard() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .build();
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry)
+        .mount("/", rocket::routes![report]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = client.get("/report").dispatch().await;
+    assert_eq!(response.into_string().await.unwrap(), "Report");
+
+    let envelopes = recorder.0.lock().unwrap();
+    let transaction = envelopes
+        .iter()
+        .flat_map(Envelope::items)
+        .find_map(|item| match item {
+            EnvelopeItem::Transaction(transaction) => Some(transaction),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(transaction.name.as_deref(), Some("report"));
+    assert_eq!(transaction.tags["report.kind"], "monthly");
+    assert_eq!(transaction.spans.len(), 1);
+    assert_eq!(transaction.spans[0].op.as_deref(), Some("db.query"));
+    let Some(Context::Trace(trace)) = transaction.contexts.get("trace") else {
+        panic!("No trace context");
+    };
+    assert_eq!(trace.status, Some(SpanStatus::DeadlineExceeded));
+}
+
+/// The request guard doesn't fail requests without a transaction
+#[rocket::async_test]
+async fn fairing_provides_transaction_guard_without_transaction() {
+    let recorder = Arc::new(Recorder::default());
+    let ignored = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0))
+        .merge(("sentry.ignore.paths", ["/report"]));
+    let disabled = Figment::from(Config::debug_default());
+
+    for figment in [ignored, disabled] {
+        let rocket_sentry = RocketSentry::builder()
+            .client_options(record_to(&recorder))
+            .build();
+        let rocket = rocket::custom(figment)
+            .attach(rocket_sentry)
+            .mount("/", rocket::routes![report]);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client.get("/report").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "Report");
+    }
+
+    assert!(recorder.transaction_names().is_empty());
+}
+
+#[rocket::get("/import")]
+fn import(sentry: &State<SentryHandle>) -> &'static str {
+    if let Err(err) = "x".parse::<u32>() {
+        sentry.capture_error(&err);
+    }
+    "Imported"
+}
+
+/// The handle in managed state captures events with the request data and counts them
+#[rocket::async_test]
+async fn fairing_manages_handle() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .build();
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry)
+        .mount("/", rocket_sentry::bind_hub(rocket::routes![import]));
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = client.get("/import?file=a.csv").dispatch().await;
+    assert_eq!(response.into_string().await.unwrap(), "Imported");
+
+    let handle = client.rocket().state::<SentryHandle>().unwrap();
+    assert!(handle.is_enabled());
+    let stats = handle.stats();
+    assert_eq!(stats.requests, 1);
+    assert_eq!(stats.transactions, 1);
+    assert_eq!(stats.events, 1);
+    assert!(handle.flush(Duration::from_secs(1)).await);
+    let envelopes = recorder.0.lock().unwrap();
+    let event = envelopes.iter().find_map(Envelope::event).unwrap();
+    let request = event.request.as_ref().unwrap();
+    assert_eq!(request.query_string.as_deref(), Some("file=a.csv"));
+}
+
+/// Ignored requests don't send transactions
+#[rocket::async_test]
+async fn fairing_ignores_requests() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0))
+        .merge(("sentry.ignore.paths", ["/health"]))
+        .merge(("sentry.ignore.mounts", ["/static"]))
+        .merge(("sentry.ignore.routes", ["index"]))
+        .merge(("sentry_trace_response_headers", true));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .build();
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry)
+        .mount("/", rocket::routes![index, health, capture])
+        .mount("/static", rocket::routes![capture]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    for uri in ["/health", "/capture", "/static/capture"] {
+        client.get(uri).dispatch().await;
+    }
+    // Ignored routes still pass the trace on
+    let response = client.get("/").dispatch().await;
+    assert!(response.headers().get_one("sentry-trace").is_some());
+
+    assert_eq!(recorder.transaction_names(), ["GET /capture"]);
+}
+
+/// Sample rules take precedence over the global rate, which may even be zero
+#[rocket::async_test]
+async fn fairing_applies_traces_sample_rules() {
+    let rules = r#"
+        [[sentry.traces_sample_rules]]
+        path = "/health"
+        rate = 0.0
+
+        [[sentry.traces_sample_rules]]
+        method = "GET"
+        rate = 1.0
+    "#;
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 0.0))
+        .merge(Toml::string(rules));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .build();
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry)
+        .mount("/", rocket::routes![index, health, capture]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    for uri in ["/", "/health", "/capture"] {
+        client.get(uri).dispatch().await;
+    }
+    // Incoming traces keep their sampling decision
+    client
+        .get("/health")
+        .header(Header::new(
+            "sentry-trace",
+            "09e04486820349518ac7b5d2adbf6ba5-b84fb8e8d7ca0a6d-1",
+        ))
+        .dispatch()
+        .await;
+
+    assert_eq!(
+        recorder.transaction_names(),
+        ["GET /", "GET /capture", "GET /health"]
+    );
+}
+
+/// The request sampler sees the request, and can defer to the other ways of sampling
+#[rocket::async_test]
+async fn fairing_runs_request_sampler() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .request_sampler(|request| match request.headers().get_one("X-Sample") {
+            Some("keep") => SamplingDecision::Keep,
+            Some("drop") => SamplingDecision::Drop,
+            _ => SamplingDecision::Defer,
+        })
+        .build();
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry)
+        .mount("/", rocket::routes![index, health, capture]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let sample = |value| Header::new("X-Sample", value);
+    client.get("/").header(sample("drop")).dispatch().await;
+    client
+        .get("/capture")
+        .header(sample("keep"))
+        .dispatch()
+        .await;
+    client.get("/health").dispatch().await;
+
+    assert_eq!(
+        recorder.transaction_names(),
+        ["GET /capture", "GET /health"]
+    );
+}
+
+/// The dynamic sampling context of an incoming trace is sent along with the transaction
+#[rocket::async_test]
+async fn fairing_passes_on_dynamic_sampling_context() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_traces_sample_rate", 1.0));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .build();
+    let rocket = rocket::custom(figment)
+        .attach(rocket_sentry)
+        .mount("/", rocket::routes![index]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    client
+        .get("/")
+        .header(Header::new(
+            "sentry-trace",
+            "09e04486820349518ac7b5d2adbf6ba5-9cf635fa5b870b3a-1",
+        ))
+        .header(Header::new(
+            "baggage",
+            "sentry-trace_id=09e04486820349518ac7b5d2adbf6ba5,sentry-public_key=upstream,\
+             sentry-release=frontend%401,sentry-sampled=true",
+        ))
+        .dispatch()
+        .await;
+
+    let envelopes = recorder.0.lock().unwrap();
+    let headers = serde_json::to_value(envelopes[0].headers()).unwrap();
+    assert_eq!(headers["trace"]["public_key"], "upstream");
+    assert_eq!(headers["trace"]["release"], "frontend@1");
+}
+
+#[rocket::get("/orders/<id>?<q>")]
+fn failing_order(id: u32, q: &str) -> Status {
+    let _ = (id, q);
+    Status::ServiceUnavailable
+}
+
+#[rocket::get("/panic")]
+fn panicking() -> &'static str {
+    panic!("Handler failed");
+}
+
+/// Error responses are reported with their request, except after a panic that already was
+#[rocket::async_test]
+async fn fairing_captures_error_responses() {
+    let figment = Figment::from(Config::debug_default())
+        .merge(SENTRY_DSN_CONFIG)
+        .merge(("sentry_capture_error_responses", true));
+    let recorder = Arc::new(Recorder::default());
+    let rocket_sentry = RocketSentry::builder()
+        .client_options(record_to(&recorder))
+        .build();
+    let rocket = rocket::custom(figment).attach(rocket_sentry).mount(
+        "/",
+        rocket_sentry::bind_hub(rocket::routes![failing_order, panicking]),
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    client
+        .get("/orders/7?q=late")
+        .header(Header::new("X-Tenant", "acme"))
+        .dispatch()
+        .await;
+    client.get("/panic").dispatch().await;
+
+    let events = recorder.events();
+    assert_eq!(events.len(), 2);
+    let event = &events[0];
+    assert_eq!(event.transaction.as_deref(), Some("GET /orders/<id>"));
+    assert_eq!(event.tags["http.status_code"], "503");
+    let Some(Context::Other(route)) = event.contexts.get("route") else {
+        panic!("No route context");
+    };
+    assert_eq!(route["name"], "failing_order");
+    let request = event.request.as_ref().unwrap();
+    assert_eq!(request.method.as_deref(), Some("GET"));
+    assert_eq!(request.query_string.as_deref(), Some("q=late"));
+    assert_eq!(request.headers["X-Tenant"], "acme");
+    // The panic event, without an error response event after it
+    assert_eq!(events[1].transaction.as_deref(), Some("GET /panic"));
+    assert!(events[1].message.is_none());
+}
@@ -1,10 +1,15 @@
 use figment::Figment;
-use rocket::Config;
-use rocket_sentry::RocketSentry;
+use rocket::error::ErrorKind;
+use rocket::{Config, Ignite, Rocket};
+use rocket_sentry::{RocketSentry, ScrubConfig};
 use sentry::{Hub, TransactionContext};
 use std::sync::Arc;
 
 const SENTRY_DSN_CONFIG: (&str, &str) = ("sentry_dsn", "https://123@sentry.io/456");
+/// Rocket won't launch in profiles other than debug without one when the `secrets` feature is
+/// enabled.
+const SECRET_KEY_CONFIG: (&str, &str) =
+    ("secret_key", "hPRYyVRiMyxpw5sBB1XeCMN1kFsDCqKvBi2QJxBVHQk=");
 
 /// Smoke test: check that sentry gets initialized by the fairing.
 #[rocket::async_test]
@@ -52,7 +57,7 @@ async fn fairing_init_with_specific_traces_sampler() {
 }
 
 async fn init_rocket_using_figment(figment: Figment) {
-    rocket::custom(figment)
+    rocket::custom(figment.join(SECRET_KEY_CONFIG))
         .attach(RocketSentry::fairing())
         .ignite()
         .await
@@ -93,3 +98,125 @@ async fn fairing_init_with_custom_rocket_profile() {
 
     assert_eq!(sentry_current_hub_environment(), profile_name); // Rocket profile name was passed to Sentry config
 }
+
+#[rocket::async_test]
+async fn fairing_init_with_sentry_environment() {
+    let figment = Figment::from(Config::release_default())
+        .join(SENTRY_DSN_CONFIG)
+        .join(("sentry_environment", "staging"));
+    init_rocket_using_figment(figment).await;
+
+    assert_eq!(sentry_current_hub_environment(), "staging"); // overrides the Rocket profile
+}
+
+#[rocket::async_test]
+async fn fairing_init_with_profile_environments() {
+    let figment = Figment::new()
+        .select("prod")
+        .join(Config::debug_default())
+        .join(SENTRY_DSN_CONFIG)
+        .join(("sentry.environments.prod", "production"));
+    init_rocket_using_figment(figment).await;
+
+    assert_eq!(sentry_current_hub_environment(), "production");
+}
+
+#[rocket::async_test]
+async fn fairing_init_with_builder_profile_environment() {
+    let figment = Figment::from(Config::release_default())
+        .join(SENTRY_DSN_CONFIG)
+        .join(SECRET_KEY_CONFIG);
+    let rocket_sentry = RocketSentry::builder()
+        .profile_environment("release", "staging")
+        .build();
+    rocket::custom(figment)
+        .attach(rocket_sentry)
+        .ignite()
+        .await
+        .expect("Rocket failed to ignite");
+
+    assert_eq!(sentry_current_hub_environment(), "staging");
+}
+
+fn assert_fairing_failed(result: Result<Rocket<Ignite>, rocket::Error>) {
+    let err = result.unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::FailedFairings(_)));
+}
+
+#[rocket::async_test]
+async fn fairing_init_required_without_dsn() {
+    let figment = Figment::from(Config::debug_default())
+        .join(("sentry_dsn", ""))
+        .join(("sentry_required", true));
+    let result = rocket::custom(figment)
+        .attach(RocketSentry::fairing())
+        .ignite()
+        .await;
+
+    assert_fairing_failed(result);
+}
+
+#[rocket::async_test]
+async fn fairing_init_with_invalid_traces_sample_rate() {
+    let figment = Figment::from(Config::debug_default())
+        .join(SENTRY_DSN_CONFIG)
+        .join(("sentry_traces_sample_rate", 1.5));
+    init_rocket_using_figment(figment.clone()).await; // Not required, so the launch goes on
+
+    let result = rocket::custom(figment)
+        .attach(RocketSentry::builder().required(true).build())
+        .ignite()
+        .await;
+
+    assert_fairing_failed(result);
+}
+
+#[rocket::async_test]
+async fn fairing_init_with_invalid_ignore_pattern() {
+    let figment = Figment::from(Config::debug_default())
+        .join(SENTRY_DSN_CONFIG)
+        .merge(("sentry.ignore.user_agents", ["("]));
+
+    // Not required, so the launch goes on
+    rocket::custom(figment.clone())
+        .attach(RocketSentry::fairing())
+        .ignite()
+        .await
+        .expect("Rocket failed to ignite");
+
+    let result = rocket::custom(figment)
+        .attach(RocketSentry::builder().required(true).build())
+        .ignite()
+        .await;
+
+    assert_fairing_failed(result);
+}
+
+#[rocket::async_test]
+async fn fairing_init_with_invalid_scrub_pattern() {
+    let figment = Figment::from(Config::debug_default()).join(SENTRY_DSN_CONFIG);
+    let scrub_config = ScrubConfig {
+        deny_header_patterns: vec!["(".to_string()],
+        ..Default::default()
+    };
+    let rocket_sentry = |required| {
+        RocketSentry::builder()
+            .scrub(scrub_config.clone())
+            .required(required)
+            .build()
+    };
+
+    // Not required, so the launch goes on
+    rocket::custom(figment.clone())
+        .attach(rocket_sentry(false))
+        .ignite()
+        .await
+        .expect("Rocket failed to ignite");
+
+    let result = rocket::custom(figment)
+        .attach(rocket_sentry(true))
+        .ignite()
+        .await;
+
+    assert_fairing_failed(result);
+}